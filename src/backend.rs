@@ -0,0 +1,128 @@
+use std::{collections::HashMap, pin::Pin};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use tokio::io::AsyncWrite;
+
+use crate::error::Error;
+
+mod docker;
+mod hijack;
+
+pub use docker::DockerBackend;
+pub use hijack::Endpoint;
+pub(crate) use hijack::connect;
+
+pub enum Chunk {
+    StdOut(Vec<u8>),
+    StdErr(Vec<u8>),
+}
+
+pub struct ExecSession {
+    pub id: String,
+    pub output: BoxStream<'static, Result<Chunk, Error>>,
+    pub input: Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+pub struct PortMapping {
+    pub private_port: u64,
+    pub public_port: Option<u64>,
+}
+
+pub struct ContainerSummary {
+    pub id: String,
+    pub names: Vec<String>,
+    pub image: String,
+    pub state: String,
+    pub status: String,
+    pub ports: Vec<PortMapping>,
+    pub labels: HashMap<String, String>,
+    pub created: DateTime<Utc>,
+}
+
+pub struct ContainerDetails {
+    pub id: String,
+    pub running: bool,
+    pub exit_code: u64,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LogQuery {
+    pub follow: bool,
+    // Number of lines from the end of the logs to start with
+    pub tail: Option<u64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    // Prefixes every line with its RFC3339 timestamp
+    pub timestamps: bool,
+}
+
+// A change reported by the daemon's events API
+pub struct ContainerEvent {
+    // `container` or `volume`
+    pub kind: String,
+    // i.e. `start` or `health_status: healthy`
+    pub action: String,
+    // Container ID or volume name
+    pub id: String,
+    // Containers include their name and exit code
+    pub attributes: HashMap<String, String>,
+    pub time: DateTime<Utc>,
+}
+
+// A status update of an image pull, usually for one of its layers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PullProgress {
+    // The layer, if the update is about one
+    pub id: Option<String>,
+    // i.e. `Downloading` or `Pull complete`
+    pub status: String,
+    // Bytes of the layer which have been downloaded or extracted
+    pub current: Option<u64>,
+    pub total: Option<u64>,
+}
+
+pub struct ContainerSpec {
+    pub name: String,
+    pub image: String,
+    // Pairs of container port and host port
+    pub ports: Vec<(u32, u32)>,
+    pub volumes: Vec<String>,
+    pub privileged: bool,
+    pub labels: HashMap<String, String>,
+    // In bytes
+    pub memory: Option<u64>,
+    pub cpus: Option<f64>,
+}
+
+#[async_trait]
+pub trait ContainerBackend: Send + Sync {
+    // Lists all containers whose name contains `name`
+    async fn list(&self, name: &str) -> Result<Vec<ContainerSummary>, Error>;
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, Error>;
+    async fn start(&self, id: &str) -> Result<(), Error>;
+    async fn stop(&self, id: &str) -> Result<(), Error>;
+    async fn restart(&self, id: &str) -> Result<(), Error>;
+    fn logs(&self, id: &str, query: &LogQuery) -> BoxStream<'_, Result<Chunk, Error>>;
+    async fn exec_interactive(
+        &self,
+        id: &str,
+        cmd: Vec<String>,
+        tty: bool,
+    ) -> Result<ExecSession, Error>;
+    async fn resize_exec(&self, exec_id: &str, width: u16, height: u16) -> Result<(), Error>;
+    async fn create(&self, spec: &ContainerSpec) -> Result<(), Error>;
+    async fn remove(&self, id: &str) -> Result<(), Error>;
+    // Works on stopped containers too, including their volumes
+    async fn read_file(&self, id: &str, path: &str) -> Result<Vec<u8>, Error>;
+    async fn write_file(&self, id: &str, path: &str, content: &[u8]) -> Result<(), Error>;
+
+    // Streams events of containers and volumes until it is dropped
+    fn events(&self) -> BoxStream<'_, Result<ContainerEvent, Error>>;
+
+    async fn image_exists(&self, image: &str) -> Result<bool, Error>;
+    fn pull(&self, image: &str) -> BoxStream<'_, Result<PullProgress, Error>>;
+    async fn remove_volume(&self, name: &str) -> Result<(), Error>;
+}
@@ -0,0 +1,501 @@
+use std::{
+    error,
+    io::{self, Read},
+    path::Path,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use futures::{future, stream::BoxStream, StreamExt, TryStreamExt};
+use serde_json::{json, Value};
+use shiplift::{
+    tty, ContainerFilter, ContainerListOptions, ContainerOptions, Docker, EventsOptions,
+    LogsOptions, PullOptions, RmContainerOptions,
+};
+
+use super::{
+    hijack::{self, Endpoint, Failure},
+    Chunk, ContainerBackend, ContainerDetails, ContainerEvent, ContainerSpec, ContainerSummary,
+    ExecSession, LogQuery, PortMapping, PullProgress,
+};
+use crate::{
+    error::{Cause, Error},
+    node::{Node, Tunnel},
+};
+
+pub struct DockerBackend {
+    docker: Docker,
+    // shiplift can't attach to the stdin of execs, so these use their own connections
+    endpoint: Endpoint,
+    _tunnel: Option<Tunnel>,
+}
+
+impl DockerBackend {
+    pub fn new(endpoint: Endpoint) -> Self {
+        let docker = match &endpoint {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Docker::unix(path.to_string_lossy().to_string()),
+            Endpoint::Tcp(addr) => Docker::host(format!("http://{}", addr).parse().unwrap()),
+            // shiplift reads TLS certificates and the default socket from the environment
+            _ => Docker::new(),
+        };
+
+        Self {
+            docker,
+            endpoint,
+            _tunnel: None,
+        }
+    }
+
+    pub async fn remote(node: &Node) -> Result<Self, Error> {
+        let tunnel = Tunnel::open(node).await?;
+        let endpoint = tunnel.endpoint.clone();
+
+        Ok(Self {
+            _tunnel: Some(tunnel),
+            ..Self::new(endpoint)
+        })
+    }
+}
+
+impl Default for DockerBackend {
+    fn default() -> Self {
+        Self {
+            docker: Docker::new(),
+            endpoint: Endpoint::from_env(),
+            _tunnel: None,
+        }
+    }
+}
+
+fn to_chunk(chunk: Result<tty::TtyChunk, shiplift::Error>, id: &str) -> Result<Chunk, Error> {
+    match chunk {
+        // Stream type 0 shows up in raw streams of TTY containers, which only have one output
+        Ok(tty::TtyChunk::StdIn(b)) | Ok(tty::TtyChunk::StdOut(b)) => Ok(Chunk::StdOut(b)),
+        Ok(tty::TtyChunk::StdErr(b)) => Ok(Chunk::StdErr(b)),
+        Err(e) => Err(classify(id, e)),
+    }
+}
+
+// Docker prefixes every line with its timestamp and a space if asked to
+fn timestamp(chunk: &Chunk) -> Option<DateTime<Utc>> {
+    let line = match chunk {
+        Chunk::StdOut(b) | Chunk::StdErr(b) => b,
+    };
+
+    let end = line.iter().position(|b| *b == b' ')?;
+    let timestamp = std::str::from_utf8(&line[..end]).ok()?;
+
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+fn strip_timestamp(chunk: Chunk) -> Chunk {
+    let strip = |b: Vec<u8>| match b.iter().position(|b| *b == b' ') {
+        Some(end) => b[end + 1..].to_vec(),
+        None => b,
+    };
+
+    match chunk {
+        Chunk::StdOut(b) => Chunk::StdOut(strip(b)),
+        Chunk::StdErr(b) => Chunk::StdErr(strip(b)),
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for DockerBackend {
+    async fn list(&self, name: &str) -> Result<Vec<ContainerSummary>, Error> {
+        let mut builder = ContainerListOptions::builder();
+        builder.all();
+        if !name.is_empty() {
+            builder.filter(vec![ContainerFilter::Name(name.to_owned())]);
+        }
+
+        match self.docker.containers().list(&builder.build()).await {
+            Ok(containers) => Ok(containers
+                .into_iter()
+                .map(|c| ContainerSummary {
+                    id: c.id,
+                    names: c.names,
+                    image: c.image,
+                    state: c.state,
+                    status: c.status,
+                    ports: c
+                        .ports
+                        .into_iter()
+                        .map(|p| PortMapping {
+                            private_port: p.private_port,
+                            public_port: p.public_port,
+                        })
+                        .collect(),
+                    labels: c.labels,
+                    created: c.created,
+                })
+                .collect()),
+            Err(e) => Err(classify(name, e)),
+        }
+    }
+
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, Error> {
+        match self.docker.containers().get(id).inspect().await {
+            Ok(details) => Ok(ContainerDetails {
+                id: details.id,
+                running: details.state.running,
+                exit_code: details.state.exit_code,
+                started_at: details.state.started_at,
+            }),
+            Err(e) => Err(classify(id, e)),
+        }
+    }
+
+    async fn start(&self, id: &str) -> Result<(), Error> {
+        self.docker
+            .containers()
+            .get(id)
+            .start()
+            .await
+            .map_err(|e| classify(id, e))
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), Error> {
+        self.docker
+            .containers()
+            .get(id)
+            .stop(None)
+            .await
+            .map_err(|e| classify(id, e))
+    }
+
+    async fn restart(&self, id: &str) -> Result<(), Error> {
+        self.docker
+            .containers()
+            .get(id)
+            .restart(None)
+            .await
+            .map_err(|e| classify(id, e))
+    }
+
+    fn logs(&self, id: &str, query: &LogQuery) -> BoxStream<'_, Result<Chunk, Error>> {
+        let id = id.to_owned();
+        let until = query.until;
+        let strip_timestamps = until.is_some() && !query.timestamps;
+
+        let tail = match query.tail {
+            Some(tail) => tail.to_string(),
+            None => "all".to_owned(),
+        };
+
+        let mut builder = LogsOptions::builder();
+        builder
+            .stdout(true)
+            .stderr(true)
+            .follow(query.follow)
+            .tail(&tail)
+            // `until` is filtered here, as shiplift doesn't support it
+            .timestamps(query.timestamps || until.is_some());
+
+        if let Some(since) = &query.since {
+            builder.since(since);
+        }
+
+        self.docker
+            .containers()
+            .get(&id)
+            .logs(&builder.build())
+            .map(move |c| to_chunk(c, &id))
+            .take_while(move |c| {
+                future::ready(match (c, until) {
+                    (Ok(chunk), Some(until)) => timestamp(chunk).map_or(true, |t| t <= until),
+                    _ => true,
+                })
+            })
+            .map(move |c| match c {
+                Ok(chunk) if strip_timestamps => Ok(strip_timestamp(chunk)),
+                c => c,
+            })
+            .boxed()
+    }
+
+    async fn exec_interactive(
+        &self,
+        id: &str,
+        cmd: Vec<String>,
+        tty: bool,
+    ) -> Result<ExecSession, Error> {
+        let mut options = json!({
+            "AttachStdin": true,
+            "AttachStdout": true,
+            "AttachStderr": true,
+            "Tty": tty,
+            "Cmd": cmd,
+        });
+        if tty {
+            options["Env"] = json!(["TERM=xterm-256color"]);
+        }
+
+        let res = hijack::request(
+            &self.endpoint,
+            "POST",
+            &format!("/containers/{}/exec", id),
+            Some(&options),
+        )
+        .await
+        .map_err(|e| classify_failure(id, e))?;
+
+        let exec_id = serde_json::from_slice::<Value>(&res)
+            .ok()
+            .and_then(|v| v["Id"].as_str().map(|i| i.to_owned()))
+            .ok_or_else(|| Error::Other {
+                name: id.to_owned(),
+                cause: "invalid exec response".into(),
+            })?;
+
+        let conn = hijack::upgrade(
+            &self.endpoint,
+            &format!("/exec/{}/start", exec_id),
+            &json!({ "Detach": false, "Tty": tty }),
+        )
+        .await
+        .map_err(|e| classify_failure(id, e))?;
+
+        let (output, input) = tokio::io::split(conn);
+        let name = id.to_owned();
+
+        Ok(ExecSession {
+            id: exec_id,
+            output: hijack::demux(output, tty)
+                .map(move |c| c.map_err(|e| classify_io(&name, e.into())))
+                .boxed(),
+            input: Box::pin(input),
+        })
+    }
+
+    async fn resize_exec(&self, exec_id: &str, width: u16, height: u16) -> Result<(), Error> {
+        hijack::request(
+            &self.endpoint,
+            "POST",
+            &format!("/exec/{}/resize?h={}&w={}", exec_id, height, width),
+            None,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| classify_failure(exec_id, e))
+    }
+
+    async fn create(&self, spec: &ContainerSpec) -> Result<(), Error> {
+        let mut builder = ContainerOptions::builder(&spec.image);
+        builder
+            .name(&spec.name)
+            .restart_policy("always", 0)
+            .privileged(spec.privileged)
+            .volumes(spec.volumes.iter().map(|v| v.as_str()).collect())
+            .labels(
+                &spec
+                    .labels
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect(),
+            );
+
+        if let Some(memory) = spec.memory {
+            builder.memory(memory);
+        }
+        if let Some(cpus) = spec.cpus {
+            builder.cpus(cpus);
+        }
+
+        for (container_port, host_port) in &spec.ports {
+            builder.expose(*container_port, "tcp", *host_port);
+        }
+
+        match self.docker.containers().create(&builder.build()).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(classify(&spec.name, e)),
+        }
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), Error> {
+        self.docker
+            .containers()
+            .get(id)
+            .remove(RmContainerOptions::builder().force(true).build())
+            .await
+            .map_err(|e| classify(id, e))
+    }
+
+    async fn read_file(&self, id: &str, path: &str) -> Result<Vec<u8>, Error> {
+        let archive = self
+            .docker
+            .containers()
+            .get(id)
+            .copy_from(Path::new(path))
+            .try_concat()
+            .await
+            .map_err(|e| classify(id, e))?;
+
+        // The daemon always wraps files in a tarball
+        let mut content = vec![];
+        let mut archive = tar::Archive::new(&archive[..]);
+        let entry = archive
+            .entries()
+            .and_then(|mut entries| entries.next().transpose())
+            .and_then(|entry| match entry {
+                Some(mut entry) => entry.read_to_end(&mut content).map(|_| true),
+                None => Ok(false),
+            });
+
+        match entry {
+            Ok(true) => Ok(content),
+            Ok(false) => Err(Error::NotFound {
+                name: id.to_owned(),
+                cause: format!("{} is empty", path).into(),
+            }),
+            Err(e) => Err(Error::Other {
+                name: id.to_owned(),
+                cause: e.into(),
+            }),
+        }
+    }
+
+    async fn write_file(&self, id: &str, path: &str, content: &[u8]) -> Result<(), Error> {
+        self.docker
+            .containers()
+            .get(id)
+            .copy_file_into(path, content)
+            .await
+            .map_err(|e| classify(id, e))
+    }
+
+    fn events(&self) -> BoxStream<'_, Result<ContainerEvent, Error>> {
+        self.docker
+            .events(&EventsOptions::builder().build())
+            .filter_map(|event| {
+                future::ready(match event {
+                    // Images, networks and the daemon are of no interest
+                    Ok(e) if e.typ == "container" || e.typ == "volume" => {
+                        Some(Ok(ContainerEvent {
+                            kind: e.typ,
+                            action: e.action,
+                            id: e.actor.id,
+                            attributes: e.actor.attributes,
+                            time: Utc.timestamp_nanos(e.time_nano as i64),
+                        }))
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(Err(classify("events", e))),
+                })
+            })
+            .boxed()
+    }
+
+    async fn image_exists(&self, image: &str) -> Result<bool, Error> {
+        match self.docker.images().get(image).inspect().await {
+            Ok(_) => Ok(true),
+            Err(shiplift::Error::Fault { code, .. }) if code.as_u16() == 404 => Ok(false),
+            Err(e) => Err(classify(image, e)),
+        }
+    }
+
+    fn pull(&self, image: &str) -> BoxStream<'_, Result<PullProgress, Error>> {
+        let (name, tag) = match image.rsplit_once(':') {
+            Some((name, tag)) => (name, tag),
+            None => (image, "latest"),
+        };
+        let image = image.to_owned();
+
+        self.docker
+            .images()
+            .pull(&PullOptions::builder().image(name).tag(tag).build())
+            .map(move |p| match p {
+                // Failures after the pull has started are reported in the stream, i.e. for missing tags
+                Ok(p) => match p["error"].as_str() {
+                    Some(error) => Err(Error::Other {
+                        name: image.to_owned(),
+                        cause: error.to_owned().into(),
+                    }),
+                    None => Ok(PullProgress {
+                        id: p["id"].as_str().map(|id| id.to_owned()),
+                        status: p["status"].as_str().unwrap_or_default().to_owned(),
+                        current: p["progressDetail"]["current"].as_u64(),
+                        total: p["progressDetail"]["total"].as_u64(),
+                    }),
+                },
+                Err(e) => Err(classify(&image, e)),
+            })
+            .boxed()
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<(), Error> {
+        self.docker
+            .volumes()
+            .get(name)
+            .delete()
+            .await
+            .map_err(|e| classify(name, e))
+    }
+}
+
+fn classify(name: &str, e: shiplift::Error) -> Error {
+    if let shiplift::Error::Fault { code, message } = &e {
+        let (code, message) = (code.as_u16(), message.to_owned());
+
+        return classify_fault(name, code, &message, e.into());
+    }
+
+    classify_io(name, e.into())
+}
+
+fn classify_failure(name: &str, e: Failure) -> Error {
+    match e {
+        Failure::Io(e) => classify_io(name, e.into()),
+        Failure::Fault(code, message) => {
+            classify_fault(name, code, &message, message.to_owned().into())
+        }
+    }
+}
+
+fn classify_fault(name: &str, code: u16, message: &str, cause: Cause) -> Error {
+    let name = name.to_owned();
+    let message = message.to_lowercase();
+
+    match code {
+        404 if message.contains("no such image") => Error::ImageMissing { name, cause },
+        404 => Error::NotFound { name, cause },
+        409 => Error::Conflict { name, cause },
+        500 if message.contains("port is already allocated")
+            || message.contains("address already in use") =>
+        {
+            Error::PortAllocated { name, cause }
+        }
+        _ => Error::Other { name, cause },
+    }
+}
+
+fn classify_io(name: &str, cause: Cause) -> Error {
+    let name = name.to_owned();
+
+    match io_error_kind(cause.as_ref()) {
+        Some(io::ErrorKind::PermissionDenied) => Error::PermissionDenied { name, cause },
+        Some(io::ErrorKind::NotFound)
+        | Some(io::ErrorKind::ConnectionRefused)
+        | Some(io::ErrorKind::ConnectionReset)
+        | Some(io::ErrorKind::TimedOut) => Error::DaemonUnreachable { name, cause },
+        _ => Error::Other { name, cause },
+    }
+}
+
+// Connection errors are wrapped by hyper, so we have to look for the underlying I/O error
+fn io_error_kind(e: &(dyn error::Error + 'static)) -> Option<io::ErrorKind> {
+    let mut source = Some(e);
+
+    while let Some(s) = source {
+        if let Some(io_error) = s.downcast_ref::<io::Error>() {
+            return Some(io_error.kind());
+        }
+
+        source = s.source();
+    }
+
+    None
+}
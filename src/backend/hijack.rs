@@ -0,0 +1,229 @@
+use std::{io, path::PathBuf};
+
+use futures::{stream, Stream};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf},
+    net::TcpStream,
+};
+
+use super::Chunk;
+
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    Tcp(String),
+    // Only shiplift's client supports TLS, so exec sessions can't use it
+    Tls(String),
+}
+
+static DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+fn env_is_set(key: &str) -> bool {
+    std::env::var(key).map_or(false, |v| !v.is_empty())
+}
+
+impl Endpoint {
+    // Mirrors the resolution of `Docker::new`, which uses TLS if `DOCKER_CERT_PATH` is set
+    pub fn from_env() -> Self {
+        match std::env::var("DOCKER_HOST") {
+            Ok(host) => match host.strip_prefix("unix://") {
+                Some(path) => Self::Unix(PathBuf::from(path)),
+                None => {
+                    let addr = host.trim_start_matches("tcp://").to_owned();
+
+                    if env_is_set("DOCKER_CERT_PATH") || env_is_set("DOCKER_TLS_VERIFY") {
+                        Self::Tls(addr)
+                    } else {
+                        Self::Tcp(addr)
+                    }
+                }
+            },
+            Err(_) => Self::Unix(PathBuf::from(DEFAULT_SOCKET)),
+        }
+    }
+}
+
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+pub(crate) async fn connect(endpoint: &Endpoint) -> io::Result<Box<dyn Connection>> {
+    match endpoint {
+        #[cfg(unix)]
+        Endpoint::Unix(path) => Ok(Box::new(tokio::net::UnixStream::connect(path).await?)),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "UNIX sockets are not supported on this platform",
+        )),
+        Endpoint::Tcp(addr) => Ok(Box::new(TcpStream::connect(addr).await?)),
+        Endpoint::Tls(addr) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "interactive sessions don't support TLS, but the daemon at {} requires it; use `--node` to connect through SSH instead",
+                addr
+            ),
+        )),
+    }
+}
+
+fn request_head(method: &str, path: &str, version: &str, body: &[u8], upgrade: bool) -> Vec<u8> {
+    let mut head = format!(
+        "{} {} {}\r\nHost: docker\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        version,
+        body.len()
+    );
+
+    if upgrade {
+        head += "Connection: Upgrade\r\nUpgrade: tcp\r\n";
+    }
+
+    head += "\r\n";
+
+    let mut req = head.into_bytes();
+    req.extend_from_slice(body);
+
+    req
+}
+
+// Reads byte by byte so that no payload after the headers is consumed
+async fn read_status(conn: &mut Box<dyn Connection>) -> io::Result<u16> {
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(conn.read_u8().await?);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+
+    head.split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response"))
+}
+
+pub enum Failure {
+    Io(io::Error),
+    Fault(u16, String),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn fault(status: u16, res: &[u8]) -> Failure {
+    let message = match serde_json::from_slice::<Value>(res) {
+        Ok(v) => v["message"].as_str().unwrap_or_default().to_owned(),
+        Err(_) => String::from_utf8_lossy(res).to_string(),
+    };
+
+    Failure::Fault(status, message)
+}
+
+// Uses HTTP/1.0 so that the daemon neither chunks the response nor keeps the connection alive
+pub async fn request(
+    endpoint: &Endpoint,
+    method: &str,
+    path: &str,
+    body: Option<&Value>,
+) -> Result<Vec<u8>, Failure> {
+    let body = match body {
+        Some(b) => b.to_string().into_bytes(),
+        None => vec![],
+    };
+
+    let mut conn = connect(endpoint).await?;
+    conn.write_all(&request_head(method, path, "HTTP/1.0", &body, false))
+        .await?;
+
+    let status = read_status(&mut conn).await?;
+
+    let mut res = vec![];
+    conn.read_to_end(&mut res).await?;
+
+    if status >= 400 {
+        return Err(fault(status, &res));
+    }
+
+    Ok(res)
+}
+
+// Returns the raw connection once the daemon has switched protocols
+pub async fn upgrade(
+    endpoint: &Endpoint,
+    path: &str,
+    body: &Value,
+) -> Result<Box<dyn Connection>, Failure> {
+    let body = body.to_string().into_bytes();
+
+    let mut conn = connect(endpoint).await?;
+    conn.write_all(&request_head("POST", path, "HTTP/1.1", &body, true))
+        .await?;
+
+    match read_status(&mut conn).await? {
+        101 | 200 => Ok(conn),
+        status => {
+            let mut res = vec![];
+            conn.read_to_end(&mut res).await.ok();
+
+            Err(fault(status, &res))
+        }
+    }
+}
+
+// Without a TTY, Docker prefixes every frame with the stream type and the frame's length
+pub fn demux(
+    output: ReadHalf<Box<dyn Connection>>,
+    tty: bool,
+) -> impl Stream<Item = io::Result<Chunk>> {
+    stream::unfold(Some(output), move |output| async move {
+        let mut output = output?;
+
+        let chunk = if tty {
+            read_raw(&mut output).await
+        } else {
+            read_frame(&mut output).await
+        };
+
+        match chunk {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(output))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+async fn read_raw(output: &mut ReadHalf<Box<dyn Connection>>) -> io::Result<Option<Chunk>> {
+    let mut buf = vec![0; 4096];
+
+    match output.read(&mut buf).await? {
+        0 => Ok(None),
+        n => {
+            buf.truncate(n);
+
+            Ok(Some(Chunk::StdOut(buf)))
+        }
+    }
+}
+
+async fn read_frame(output: &mut ReadHalf<Box<dyn Connection>>) -> io::Result<Option<Chunk>> {
+    let mut header = [0; 8];
+    match output.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let mut frame = vec![0; len];
+    output.read_exact(&mut frame).await?;
+
+    match header[0] {
+        2 => Ok(Some(Chunk::StdErr(frame))),
+        _ => Ok(Some(Chunk::StdOut(frame))),
+    }
+}
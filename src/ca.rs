@@ -0,0 +1,135 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use chrono::{Duration, Utc};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose, SanType,
+};
+
+use crate::Error;
+
+static CA_COMMON_NAME: &str = "pojde CA";
+static CA_VALIDITY_DAYS: i64 = 3650;
+// Apple platforms reject server certificates which are valid for longer than 825 days
+static LEAF_VALIDITY_DAYS: i64 = 825;
+
+// Backdated by a day to tolerate clocks of clients which are behind the host
+fn validity(days: i64) -> (chrono::DateTime<Utc>, chrono::DateTime<Utc>) {
+    let now = Utc::now();
+
+    (now - Duration::days(1), now + Duration::days(days))
+}
+
+// Every CA and leaf needs a distinct serial, or browsers will refuse certificates after a reset
+fn serial_number() -> u64 {
+    Utc::now().timestamp_nanos() as u64
+}
+
+fn other(name: &str, cause: rcgen::RcgenError) -> Error {
+    Error::Other {
+        name: name.to_owned(),
+        cause: cause.into(),
+    }
+}
+
+pub struct CertificateAuthority {
+    cert: Certificate,
+    cert_pem: String,
+}
+
+pub struct LeafCertificate {
+    pub cert_pem: String,
+    pub key_pem: String,
+}
+
+impl CertificateAuthority {
+    pub fn generate() -> Result<Self, Error> {
+        let (not_before, not_after) = validity(CA_VALIDITY_DAYS);
+
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, CA_COMMON_NAME);
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        params.not_before = not_before;
+        params.not_after = not_after;
+        params.serial_number = Some(serial_number());
+
+        let cert = Certificate::from_params(params).map_err(|e| other(CA_COMMON_NAME, e))?;
+        let cert_pem = cert.serialize_pem().map_err(|e| other(CA_COMMON_NAME, e))?;
+
+        Ok(Self { cert, cert_pem })
+    }
+
+    pub fn from_pem(cert_pem: &str, key_pem: &str) -> Result<Self, Error> {
+        let key_pair = KeyPair::from_pem(key_pem).map_err(|e| other(CA_COMMON_NAME, e))?;
+        let params = CertificateParams::from_ca_cert_pem(cert_pem, key_pair)
+            .map_err(|e| other(CA_COMMON_NAME, e))?;
+
+        Ok(Self {
+            cert: Certificate::from_params(params).map_err(|e| other(CA_COMMON_NAME, e))?,
+            // Re-serializing would change the certificate which browsers already trust
+            cert_pem: cert_pem.to_owned(),
+        })
+    }
+
+    pub fn cert_pem(self: &Self) -> &str {
+        &self.cert_pem
+    }
+
+    pub fn key_pem(self: &Self) -> String {
+        self.cert.serialize_private_key_pem()
+    }
+
+    // Issues a server certificate for an instance, which is reachable by its name and on localhost
+    // `host` is where the instance is reached from, i.e. the node it runs on
+    pub fn issue(self: &Self, name: &str, host: &str) -> Result<LeafCertificate, Error> {
+        let (not_before, not_after) = validity(LEAF_VALIDITY_DAYS);
+
+        let mut params = CertificateParams::default();
+        params.subject_alt_names = subject_alt_names(name, host);
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        params.not_before = not_before;
+        params.not_after = not_after;
+        params.serial_number = Some(serial_number());
+
+        let cert = Certificate::from_params(params).map_err(|e| other(name, e))?;
+
+        Ok(LeafCertificate {
+            cert_pem: cert
+                .serialize_pem_with_signer(&self.cert)
+                .map_err(|e| other(name, e))?,
+            key_pem: cert.serialize_private_key_pem(),
+        })
+    }
+}
+
+// Hosts which are IP addresses, i.e. of remote nodes, need an IP SAN, as browsers don't accept them as DNS names
+pub fn subject_alt_names(name: &str, host: &str) -> Vec<SanType> {
+    let mut names = vec![
+        SanType::DnsName(name.to_owned()),
+        SanType::DnsName("localhost".to_owned()),
+        SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+    ];
+
+    let host = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => SanType::IpAddress(ip),
+        Err(_) => SanType::DnsName(host.to_owned()),
+    };
+    if !names.contains(&host) {
+        names.push(host);
+    }
+
+    names
+}
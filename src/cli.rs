@@ -1,18 +1,39 @@
-use std::str::from_utf8;
-use std::str::FromStr;
+use std::fs;
+use std::io::{stderr, stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
 
+use chrono::{DateTime, SecondsFormat, Utc};
 use clap::{crate_authors, crate_description, crate_version, AppSettings, Clap};
-use futures::future::try_join_all;
+use crossterm::terminal;
 use futures::StreamExt;
-use pojde_rs::instances::Instances;
+use pojde_rs::backend::{Chunk, ExecSession, LogQuery};
+use pojde_rs::forward::{forward, Direction, ForwardSpec};
+use pojde_rs::instances::{
+    container_name, port_range, ApplyOptions, InstanceHealth, InstanceStatus, Instances, Label,
+    LifecycleAction, RemoveScope, Selector,
+};
+use pojde_rs::manifest::{plan, plan_sync, sync, Change, Manifest};
+use pojde_rs::node::Node;
+use pojde_rs::output::{
+    serialize, serialize_item, EventOutput, InstanceOutput, OperationResult, OutputFormat,
+};
+use pojde_rs::plan::{diff, PlannedChange};
 use pojde_rs::update::update;
-use shiplift::Docker;
+use pojde_rs::Error;
 use spinners::{Spinner, Spinners};
 use tabled::Style;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
 use tokio::task::spawn_blocking;
+use tokio::time::interval;
 
 use tabled::{Table, Tabled};
 
+static RESIZE_INTERVAL: Duration = Duration::from_millis(250);
+static CA_CERT_FILE: &str = "ca.pem";
+
 #[derive(Clap)]
 #[clap(
     version = crate_version!(),
@@ -30,7 +51,17 @@ struct Opts {
         about = "Remote host to execute on, in format user@host:port",
         global = true
     )]
-    node: Option<String>,
+    node: Option<Node>,
+
+    #[clap(
+        short,
+        long,
+        about = "Output format",
+        possible_values = &["table", "wide", "json", "yaml"],
+        default_value = "table",
+        global = true
+    )]
+    output: OutputFormat,
 }
 
 #[derive(Clap)]
@@ -73,11 +104,29 @@ enum ModificationCommands {
     setting = AppSettings::ColoredHelp,
 )]
 struct Apply {
-    #[clap(about = "Name of the instance to apply")]
-    name: String,
-    #[clap(about = "Starting port for the instance")]
-    start_port: i32,
-    #[clap(short, long, about = "Skip confirmation prompts")]
+    #[clap(
+        about = "Name of the instance to apply",
+        required_unless_present = "file"
+    )]
+    name: Option<String>,
+    #[clap(about = "Starting port for the instance, a free one is picked if omitted")]
+    start_port: Option<u64>,
+    #[clap(
+        short,
+        long,
+        about = "Apply all instances of a YAML or TOML manifest",
+        conflicts_with_all = &["name", "start-port", "recreate", "isolate", "privileged", "modules"]
+    )]
+    file: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Remove instances which aren't listed in the manifest",
+        requires = "file"
+    )]
+    prune: bool,
+    #[clap(long, about = "Print the changes instead of applying them")]
+    dry_run: bool,
+    #[clap(long, about = "Skip confirmation prompts")]
     force: bool,
     #[clap(short, long, about = "Pull latest image")]
     upgrade: bool,
@@ -87,6 +136,13 @@ struct Apply {
     isolate: bool,
     #[clap(short, long, about = "Run in privileged mode")]
     privileged: bool,
+    #[clap(
+        short,
+        long = "module",
+        about = "Language module to install, can be repeated",
+        possible_values = &["cpp", "go", "js", "python", "java", "csharp", "rust", "ruby", "r", "tex"]
+    )]
+    modules: Vec<String>,
 }
 
 #[derive(Clap)]
@@ -95,8 +151,19 @@ struct Apply {
     setting = AppSettings::ColoredHelp,
 )]
 struct Remove {
-    #[clap(about = "Name(s) of the instance(s) to remove")]
+    #[clap(
+        about = "Name(s) or glob(s) of the instance(s) to remove, i.e. team-*",
+        required_unless_present_any = &["all-instances", "selector"]
+    )]
     names: Vec<String>,
+    #[clap(long, about = "Remove all instances")]
+    all_instances: bool,
+    #[clap(
+        short = 'l',
+        long,
+        about = "Only remove instances with the label, in format key=value"
+    )]
+    selector: Vec<Label>,
     #[clap(short, long, about = "Skip confirmation prompts")]
     force: bool,
     #[clap(short, long, about = "Remove customizations")]
@@ -113,6 +180,8 @@ struct Remove {
     deb_cache: bool,
     #[clap(short, long, about = "Remove everything")]
     all: bool,
+    #[clap(long, about = "Print the changes instead of removing anything")]
+    dry_run: bool,
 }
 
 #[derive(Clap)]
@@ -120,7 +189,10 @@ struct Remove {
     about = "List all instances",
     setting = AppSettings::ColoredHelp,
 )]
-struct List {}
+struct List {
+    #[clap(short, long, about = "Show images and services, same as --output wide")]
+    wide: bool,
+}
 
 // Lifecycle commands
 #[derive(Clap)]
@@ -147,8 +219,19 @@ enum LifecycleCommands {
     setting = AppSettings::ColoredHelp,
 )]
 struct Start {
-    #[clap(about = "Name(s) of the instance(s) to start", required = true)]
+    #[clap(
+        about = "Name(s) or glob(s) of the instance(s) to start, i.e. team-*",
+        required_unless_present_any = &["all", "selector"]
+    )]
     names: Vec<String>,
+    #[clap(short, long, about = "Start all instances")]
+    all: bool,
+    #[clap(
+        short = 'l',
+        long,
+        about = "Only start instances with the label, in format key=value"
+    )]
+    selector: Vec<Label>,
 }
 
 #[derive(Clap)]
@@ -157,8 +240,19 @@ struct Start {
     setting = AppSettings::ColoredHelp,
 )]
 struct Stop {
-    #[clap(about = "Name(s) of the instance(s) to stop", required = true)]
+    #[clap(
+        about = "Name(s) or glob(s) of the instance(s) to stop, i.e. team-*",
+        required_unless_present_any = &["all", "selector"]
+    )]
     names: Vec<String>,
+    #[clap(short, long, about = "Stop all instances")]
+    all: bool,
+    #[clap(
+        short = 'l',
+        long,
+        about = "Only stop instances with the label, in format key=value"
+    )]
+    selector: Vec<Label>,
 }
 
 #[derive(Clap)]
@@ -167,8 +261,19 @@ struct Stop {
     setting = AppSettings::ColoredHelp,
 )]
 struct Restart {
-    #[clap(about = "Name(s) of the instance(s) to restart", required = true)]
+    #[clap(
+        about = "Name(s) or glob(s) of the instance(s) to restart, i.e. team-*",
+        required_unless_present_any = &["all", "selector"]
+    )]
     names: Vec<String>,
+    #[clap(short, long, about = "Restart all instances")]
+    all: bool,
+    #[clap(
+        short = 'l',
+        long,
+        about = "Only restart instances with the label, in format key=value"
+    )]
+    selector: Vec<Label>,
 }
 
 // Utility commands
@@ -188,6 +293,9 @@ enum UtilityCommands {
     Logs(Logs),
     Enter(Enter),
     Forward(Forward),
+    Events(Events),
+    Services(Services),
+    Open(Open),
 }
 
 #[derive(Clap)]
@@ -198,6 +306,38 @@ enum UtilityCommands {
 struct Logs {
     #[clap(about = "Name of the instance to get logs for")]
     name: String,
+    #[clap(short, long, about = "Keep streaming new logs")]
+    follow: bool,
+    // `-n` is taken by the global `--node`
+    #[clap(long, about = "Number of lines to show from the end of the logs")]
+    tail: Option<u64>,
+    #[clap(
+        long,
+        about = "Show logs since a duration ago or a RFC3339 timestamp, i.e. 10m or 2021-07-01T10:00:00Z",
+        parse(try_from_str = parse_time)
+    )]
+    since: Option<DateTime<Utc>>,
+    #[clap(
+        long,
+        about = "Show logs until a duration ago or a RFC3339 timestamp, i.e. 10m or 2021-07-01T10:00:00Z",
+        parse(try_from_str = parse_time)
+    )]
+    until: Option<DateTime<Utc>>,
+    #[clap(short, long, about = "Prefix every line with its timestamp")]
+    timestamps: bool,
+}
+
+// Durations are relative to now, i.e. `10m` means ten minutes ago
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(duration) = humantime::parse_duration(s) {
+        return chrono::Duration::from_std(duration)
+            .map(|d| Utc::now() - d)
+            .map_err(|e| e.to_string());
+    }
+
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| format!("invalid duration or RFC3339 timestamp {:?}", s))
 }
 
 #[derive(Clap)]
@@ -208,6 +348,11 @@ struct Logs {
 struct Enter {
     #[clap(about = "Name of the instance to enter")]
     name: String,
+    #[clap(
+        about = "Command to run instead of the login shell, i.e. -- htop",
+        last = true
+    )]
+    cmd: Vec<String>,
 }
 
 #[derive(Clap)]
@@ -218,27 +363,46 @@ struct Enter {
 struct Forward {
     #[clap(about = "Name of the instance to forward from or to")]
     name: String,
-    #[clap(about = "Local address:remote address to forward, i.e. localhost:5000:localhost:5000")]
-    address: Vec<String>,
+    #[clap(
+        about = "Local address:remote address to forward, i.e. localhost:5000:localhost:5000",
+        required = true
+    )]
+    address: Vec<ForwardSpec>,
     #[clap(short, long, about = "Peer to forward to", possible_values = &["local","remote"], default_value = "local")]
     direction: Direction,
 }
 
-enum Direction {
-    Local,
-    Remote,
-}
+#[derive(Clap)]
+#[clap(
+    about = "Stream changes of instances as they happen",
+    setting = AppSettings::ColoredHelp,
+)]
+struct Events {}
 
-impl FromStr for Direction {
-    type Err = &'static str;
+#[derive(Clap)]
+#[clap(
+    about = "List the services of an instance and their URLs",
+    setting = AppSettings::ColoredHelp,
+)]
+struct Services {
+    #[clap(about = "Name of the instance to list the services of")]
+    name: String,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "local" => Ok(Self::Local),
-            "remote" => Ok(Self::Remote),
-            _ => Err("no match"),
-        }
-    }
+#[derive(Clap)]
+#[clap(
+    about = "Open a service of an instance in the browser",
+    setting = AppSettings::ColoredHelp,
+)]
+struct Open {
+    #[clap(about = "Name of the instance to open")]
+    name: String,
+    #[clap(
+        about = "Service to open",
+        default_value = "code-server",
+        possible_values = &["cockpit", "code-server", "theia", "jupyter-lab", "ttyd", "novnc", "ssh"]
+    )]
+    service: String,
 }
 
 // Miscellaneous commands
@@ -297,157 +461,825 @@ struct Instance {
     ports: String,
 }
 
+#[derive(Tabled)]
+struct OperationRow {
+    #[header("NAME")]
+    name: String,
+    #[header("RESULT")]
+    result: String,
+}
+
+#[derive(Tabled)]
+struct WideInstance {
+    #[header("NAME")]
+    name: String,
+    #[header("STATUS")]
+    status: String,
+    #[header("PORTS")]
+    ports: String,
+    #[header("IMAGE")]
+    image: String,
+    #[header("CREATED")]
+    created: String,
+    #[header("SERVICES")]
+    services: String,
+}
+
+#[derive(Tabled)]
+struct ServiceRow {
+    #[header("SERVICE")]
+    title: String,
+    #[header("PORT")]
+    port: u64,
+    #[header("URL")]
+    url: String,
+}
+
+fn format_ports(instance: &pojde_rs::instances::Instance) -> String {
+    match (instance.start_port, instance.end_port) {
+        (Some(start_port), Some(end_port)) => start_port.to_string() + "-" + &end_port.to_string(),
+        _ => String::new(),
+    }
+}
+
+impl From<&pojde_rs::instances::Instance> for Instance {
+    fn from(i: &pojde_rs::instances::Instance) -> Self {
+        Self {
+            name: i.name.to_owned(),
+            status: format_status(i),
+            ports: format_ports(i),
+        }
+    }
+}
+
+impl From<&pojde_rs::instances::Instance> for WideInstance {
+    fn from(i: &pojde_rs::instances::Instance) -> Self {
+        Self {
+            name: i.name.to_owned(),
+            status: format_status(i),
+            ports: format_ports(i),
+            image: i.image.to_owned(),
+            created: i.created.format("%Y-%m-%d %H:%M:%S").to_string(),
+            services: i
+                .services()
+                .iter()
+                .map(|s| format!("{}:{}", s.id, s.port))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+fn format_status(instance: &pojde_rs::instances::Instance) -> String {
+    let mut status = instance.status.to_string();
+
+    if let Some(uptime) = instance.uptime {
+        status += &format!(
+            ", up {}",
+            humantime::format_duration(Duration::from_secs(uptime.as_secs()))
+        );
+    }
+
+    if let Some(health) = instance.health {
+        status += &format!(" ({})", health);
+    }
+
+    status
+}
+
+fn status_color(instance: &pojde_rs::instances::Instance) -> &'static str {
+    match (instance.status, instance.health) {
+        (_, Some(InstanceHealth::Unhealthy)) => "\x1b[31m",
+        (InstanceStatus::Running, _) => "\x1b[32m",
+        (InstanceStatus::Created, _)
+        | (InstanceStatus::Paused, _)
+        | (InstanceStatus::Restarting, _) => "\x1b[33m",
+        (InstanceStatus::Exited(_), _)
+        | (InstanceStatus::Dead, _)
+        | (InstanceStatus::Removing, _) => "\x1b[31m",
+    }
+}
+
+// Colors whole rows so that the column widths calculated by `tabled` stay intact
+fn colorize_rows(table: &str, instances: &[pojde_rs::instances::Instance]) -> String {
+    table
+        .lines()
+        .map(|line| {
+            let name = line.split('│').nth(1).map(|c| c.trim());
+
+            match instances.iter().find(|i| Some(i.name.as_str()) == name) {
+                Some(i) => format!("{}{}\x1b[0m\n", status_color(i), line),
+                None => line.to_owned() + "\n",
+            }
+        })
+        .collect()
+}
+
+fn fail(message: &str, e: Error) -> ! {
+    eprintln!("{}: {}", message, e);
+
+    exit(e.exit_code())
+}
+
+// Spinners would corrupt machine-readable output
+fn spin(output: OutputFormat, message: String) -> Option<Spinner> {
+    if output.is_machine_readable() {
+        None
+    } else {
+        Some(Spinner::new(Spinners::Dots, message))
+    }
+}
+
+fn stop_spinner(sp: Option<Spinner>) {
+    if let Some(sp) = sp {
+        sp.stop();
+        print!("{}{}", ansi_escapes::CursorLeft, ansi_escapes::EraseLine);
+    }
+}
+
+fn succeeded(names: &[String], operation: &str) -> Vec<OperationResult> {
+    names
+        .iter()
+        .map(|name| OperationResult::new(name, operation, &Ok(())))
+        .collect()
+}
+
+// Prints `message` for humans or the results for machines
+fn report(output: OutputFormat, results: &[OperationResult], message: &str) {
+    if !output.is_machine_readable() {
+        println!("{}", message);
+
+        return;
+    }
+
+    match serialize(output, results) {
+        Ok(s) => print!("{}", s),
+        Err(e) => fail("Could not serialize results", e),
+    }
+}
+
+// Prints the changes as a diff for humans or as a list for machines
+fn print_plan(output: OutputFormat, res: Result<Vec<PlannedChange>, Error>) {
+    let changes = match res {
+        Ok(changes) => changes,
+        Err(e) => fail("Could not plan changes", e),
+    };
+
+    if output.is_machine_readable() {
+        match serialize(output, &changes) {
+            Ok(s) => print!("{}", s),
+            Err(e) => fail("Could not serialize changes", e),
+        }
+    } else {
+        print!("{}", diff(&changes, atty::is(atty::Stream::Stdout)));
+    }
+}
+
+// A shared cause keeps its specific exit code, mixed causes fall back to 1
+fn exit_on_failures(res: &[(String, Result<(), Error>)]) {
+    let mut codes = res
+        .iter()
+        .filter_map(|(_, res)| res.as_ref().err().map(|e| e.exit_code()))
+        .collect::<Vec<_>>();
+    codes.sort_unstable();
+    codes.dedup();
+
+    match codes.as_slice() {
+        [] => {}
+        [code] => exit(*code),
+        _ => exit(1),
+    }
+}
+
+// Converges the instances to the manifest and prints the result of each change
+async fn apply_manifest(
+    instances: &Instances,
+    output: OutputFormat,
+    path: &Path,
+    prune: bool,
+    upgrade: bool,
+    force: bool,
+    dry_run: bool,
+) {
+    let manifest = match Manifest::load(path) {
+        Ok(manifest) => manifest,
+        Err(e) => fail("Could not load manifest", e),
+    };
+
+    let existing = match instances.get_instances().await {
+        Ok(existing) => existing,
+        Err(e) => fail("Could not get instances", e),
+    };
+
+    let changes = plan(&existing, &manifest, prune);
+
+    if dry_run {
+        print_plan(
+            output,
+            plan_sync(instances, &manifest, &changes, upgrade).await,
+        );
+
+        return;
+    }
+
+    let destructive = changes
+        .iter()
+        .filter(|(_, change)| matches!(change, Change::Recreate | Change::Prune))
+        .collect::<Vec<_>>();
+    if !destructive.is_empty() && !force {
+        eprintln!("The following will be destroyed:");
+        destructive.iter().for_each(|(name, change)| {
+            eprintln!("  container {} ({})", container_name(name), change)
+        });
+
+        if !confirm("Changes outside of volumes will be lost. Continue?") {
+            return;
+        }
+    }
+
+    let sp = spin(
+        output,
+        format!("Applying {} instance(s) ...", manifest.instances.len()),
+    );
+
+    let res = sync(instances, &manifest, &changes, upgrade).await;
+
+    stop_spinner(sp);
+
+    if output.is_machine_readable() {
+        let results = res
+            .iter()
+            .zip(changes.iter())
+            .map(|((name, res), (_, change))| OperationResult::new(name, &change.to_string(), res))
+            .collect::<Vec<_>>();
+
+        report(output, &results, "");
+    } else {
+        print!(
+            "{}",
+            Table::new(
+                res.iter()
+                    .zip(changes.iter())
+                    .map(|((name, res), (_, change))| {
+                        OperationRow {
+                            name: name.to_owned(),
+                            result: match (res, change) {
+                                (Ok(_), Change::Create) => "created".to_owned(),
+                                (Ok(_), Change::Recreate) => "recreated".to_owned(),
+                                (Ok(_), Change::Update) => "up to date".to_owned(),
+                                (Ok(_), Change::Prune) => "pruned".to_owned(),
+                                (Err(e), _) => format!("failed: {}", e),
+                            },
+                        }
+                    })
+            )
+            .with(Style::pseudo())
+        );
+    }
+
+    exit_on_failures(&res);
+}
+
+// Hands the URL to the desktop's default handler
+async fn open_url(url: &str) -> Result<(), Error> {
+    #[cfg(target_os = "macos")]
+    let mut cmd = Command::new("open");
+    // `explorer` exits with 1 even if it opened the URL; the empty argument is the window title
+    #[cfg(target_os = "windows")]
+    let mut cmd = Command::new("cmd");
+    #[cfg(target_os = "windows")]
+    cmd.args(&["/C", "start", ""]);
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let mut cmd = Command::new("xdg-open");
+
+    match cmd.arg(url).status().await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::Other {
+            name: url.to_owned(),
+            cause: format!("the handler exited with {}", status).into(),
+        }),
+        Err(e) => Err(Error::Other {
+            name: url.to_owned(),
+            cause: e.into(),
+        }),
+    }
+}
+
+async fn connect(node: Option<&Node>) -> Instances {
+    match Instances::connect(node).await {
+        Ok(instances) => instances,
+        Err(e) => fail("Could not connect", e),
+    }
+}
+
+// Exits if nothing matches, as commands would silently do nothing otherwise
+async fn resolve(instances: &Instances, selector: &Selector) -> Vec<String> {
+    match instances.resolve(selector).await {
+        Ok(names) if names.is_empty() => fail(
+            "Could not select instances",
+            Error::NotFound {
+                name: selector.to_string(),
+                cause: "no instances match".into(),
+            },
+        ),
+        Ok(names) => names,
+        Err(e) => fail("Could not select instances", e),
+    }
+}
+
+async fn attach(instances: &Instances, mut session: ExecSession) -> Result<(), Error> {
+    let mut size = terminal::size().unwrap_or((80, 24));
+    instances.resize(&session.id, size.0, size.1).await?;
+
+    terminal::enable_raw_mode().ok();
+    let _raw_mode = scopeguard::guard((), |_| {
+        terminal::disable_raw_mode().ok();
+    });
+
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut stdin_open = true;
+    let mut buf = vec![0; 1024];
+
+    // Polling works on all platforms, unlike `SIGWINCH`
+    let mut resize_interval = interval(RESIZE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            chunk = session.output.next() => match chunk {
+                Some(Ok(Chunk::StdOut(b))) | Some(Ok(Chunk::StdErr(b))) => {
+                    stdout.write_all(&b).await.ok();
+                    stdout.flush().await.ok();
+                }
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            },
+            n = stdin.read(&mut buf), if stdin_open => match n {
+                Ok(0) | Err(_) => {
+                    stdin_open = false;
+                    session.input.shutdown().await.ok();
+                }
+                Ok(n) => {
+                    if session.input.write_all(&buf[..n]).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            },
+            _ = resize_interval.tick() => {
+                if let Ok(new_size) = terminal::size() {
+                    if new_size != size {
+                        size = new_size;
+                        instances.resize(&session.id, size.0, size.1).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Prompts on stderr so that stdout only contains the output
+fn confirm(prompt: &str) -> bool {
+    eprint!("{} [y/N] ", prompt);
+    stderr().flush().unwrap();
+
+    let mut answer = String::new();
+    if stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
 #[tokio::main]
 pub async fn main() {
     let opts = Opts::parse();
 
     match opts.subcmd {
         Topics::Modify(t) => {
-            let instances = Instances {
-                docker: Docker::new(),
-            };
+            let instances = connect(opts.node.as_ref()).await;
 
             match t.subcmd {
-                ModificationCommands::Apply(_) => todo!(),
-                ModificationCommands::Remove(_) => todo!(),
-                ModificationCommands::List(_) => match instances.get_instances().await {
-                    Ok(containers) => print!(
-                        "{}",
-                        Table::new(containers.iter().map(|c| {
-                            if let (Some(start_port), Some(end_port)) = (c.start_port, c.end_port) {
-                                return Instance {
-                                    name: c.name.to_owned(),
-                                    status: c.status.to_owned(),
-                                    ports: start_port.to_string() + "-" + &end_port.to_string(),
-                                };
-                            }
+                ModificationCommands::Apply(c) => {
+                    let (name, start_port) = match (c.file, c.name, c.start_port) {
+                        (Some(file), _, _) => {
+                            apply_manifest(
+                                &instances,
+                                opts.output,
+                                &file,
+                                c.prune,
+                                c.upgrade,
+                                c.force,
+                                c.dry_run,
+                            )
+                            .await;
 
-                            Instance {
-                                name: c.name.to_owned(),
-                                status: c.status.to_owned(),
-                                ports: String::new(),
+                            return;
+                        }
+                        (None, Some(name), Some(start_port)) => (name, start_port),
+                        (None, Some(name), None) => match instances.allocate_ports(&name).await {
+                            Ok(start_port) => (name, start_port),
+                            Err(e) => fail(&format!("Could not allocate ports for {:?}", name), e),
+                        },
+                        // Enforced by clap
+                        _ => unreachable!(),
+                    };
+
+                    let options = ApplyOptions {
+                        start_port,
+                        upgrade: c.upgrade,
+                        recreate: c.recreate,
+                        isolate: c.isolate,
+                        privileged: c.privileged,
+                        modules: c.modules,
+                        ..ApplyOptions::default()
+                    };
+
+                    if c.dry_run {
+                        print_plan(opts.output, instances.plan_apply(&name, &options).await);
+
+                        return;
+                    }
+
+                    if c.recreate && !c.force {
+                        match instances.exists(&name).await {
+                            Ok(true) => {
+                                let prompt = format!(
+                                    "Re-creating {:?} will discard all changes outside of its volumes. Continue?",
+                                    name
+                                );
+
+                                if !confirm(&prompt) {
+                                    return;
+                                }
                             }
-                        }))
-                        .with(Style::pseudo())
-                        .to_string()
-                    ),
-                    Err(e) => eprintln!("Could not list instances: {}", e),
-                },
-            }
-        }
-        Topics::Cycle(t) => {
-            let instances = Instances {
-                docker: Docker::new(),
-            };
+                            Ok(false) => {}
+                            Err(e) => fail(&format!("Could not apply {:?}", name), e),
+                        }
+                    }
 
-            match t.subcmd {
-                LifecycleCommands::Start(c) => {
-                    let sp =
-                        Spinner::new(Spinners::Dots, format!("Starting {:?} ...", c.names).into());
+                    let sp = spin(opts.output, format!("Applying {:?} ...", name));
 
-                    let res = try_join_all(
-                        c.names
-                            .iter()
-                            .map(|name| instances.start(name))
-                            .collect::<Vec<_>>(),
-                    )
-                    .await;
+                    let res = instances.apply(&name, &options).await;
 
-                    sp.stop();
-                    print!("{}{}", ansi_escapes::CursorLeft, ansi_escapes::EraseLine);
+                    stop_spinner(sp);
 
                     match res {
-                        Ok(_) => println!("Started {:?}.", c.names),
-                        Err(e) => eprintln!("Could not start {:?}: {}", c.names, e),
+                        Ok(start_port) => report(
+                            opts.output,
+                            &[OperationResult::new(&name, "apply", &Ok(()))],
+                            &format!(
+                                "Applied {:?} with ports {}-{}.",
+                                name,
+                                start_port,
+                                port_range(start_port).1
+                            ),
+                        ),
+                        Err(e) => fail(&format!("Could not apply {:?}", name), e),
                     }
                 }
-                LifecycleCommands::Stop(c) => {
-                    let sp =
-                        Spinner::new(Spinners::Dots, format!("Stopping {:?} ...", c.names).into());
+                ModificationCommands::Remove(c) => {
+                    let scope = if c.all {
+                        RemoveScope::all()
+                    } else {
+                        RemoveScope {
+                            customizations: c.customizations,
+                            preferences: c.preferences,
+                            security: c.security,
+                            user_data: c.user_data,
+                            transfer: c.transfer,
+                            deb_cache: c.deb_cache,
+                        }
+                    };
 
-                    let res = try_join_all(
-                        c.names
-                            .iter()
-                            .map(|name| instances.stop(name))
-                            .collect::<Vec<_>>(),
+                    let names = resolve(
+                        &instances,
+                        &Selector {
+                            all: c.all_instances,
+                            patterns: c.names,
+                            labels: c.selector,
+                        },
                     )
                     .await;
 
-                    sp.stop();
-                    print!("{}{}", ansi_escapes::CursorLeft, ansi_escapes::EraseLine);
+                    if c.dry_run {
+                        print_plan(opts.output, instances.plan_remove(&names, &scope).await);
 
-                    match res {
-                        Ok(_) => println!("Stopped {:?}.", c.names),
-                        Err(e) => eprintln!("Could not stop {:?}: {}", c.names, e),
+                        return;
                     }
-                }
-                LifecycleCommands::Restart(c) => {
-                    let sp = Spinner::new(
-                        Spinners::Dots,
-                        format!("Restarting {:?} ...", c.names).into(),
-                    );
-
-                    let res = try_join_all(
-                        c.names
+
+                    if !c.force {
+                        let changes = match instances.plan_remove(&names, &scope).await {
+                            Ok(changes) => changes,
+                            Err(e) => fail(&format!("Could not remove {:?}", names), e),
+                        };
+
+                        eprintln!("The following will be destroyed:");
+                        changes
                             .iter()
-                            .map(|name| instances.restart(name))
-                            .collect::<Vec<_>>(),
-                    )
-                    .await;
+                            .for_each(|change| eprintln!("  {} {}", change.resource, change.name));
+
+                        let kept = scope
+                            .volumes(&names)
+                            .into_iter()
+                            .filter(|volume| !changes.iter().any(|change| &change.name == volume))
+                            .collect::<Vec<_>>();
+                        if !kept.is_empty() {
+                            eprintln!(
+                                "Other instances still use {}, so it will be kept.",
+                                kept.join(", ")
+                            );
+                        }
 
-                    sp.stop();
-                    print!("{}{}", ansi_escapes::CursorLeft, ansi_escapes::EraseLine);
+                        if !confirm("Continue?") {
+                            return;
+                        }
+                    }
+
+                    let sp = spin(opts.output, format!("Removing {:?} ...", names));
+
+                    let res = instances.remove(&names, &scope).await;
+
+                    stop_spinner(sp);
 
                     match res {
-                        Ok(_) => println!("Restarted {:?}.", c.names),
-                        Err(e) => eprintln!("Could not restart {:?}: {}", c.names, e),
+                        Ok(volumes) if volumes.is_empty() => report(
+                            opts.output,
+                            &succeeded(&names, "remove"),
+                            &format!("Removed {:?}.", names),
+                        ),
+                        Ok(volumes) => report(
+                            opts.output,
+                            &succeeded(&names, "remove"),
+                            &format!("Removed {:?} and volumes {}.", names, volumes.join(", ")),
+                        ),
+                        Err(e) => fail(&format!("Could not remove {:?}", names), e),
                     }
                 }
+                ModificationCommands::List(c) => match instances.get_instances().await {
+                    Ok(containers) => {
+                        let output = match (opts.output, c.wide) {
+                            (OutputFormat::Table, true) => OutputFormat::Wide,
+                            (output, _) => output,
+                        };
+
+                        let table = match output {
+                            OutputFormat::Json | OutputFormat::Yaml => {
+                                let listed = containers
+                                    .iter()
+                                    .map(InstanceOutput::from)
+                                    .collect::<Vec<_>>();
+
+                                match serialize(output, &listed) {
+                                    Ok(s) => print!("{}", s),
+                                    Err(e) => fail("Could not list instances", e),
+                                }
+
+                                return;
+                            }
+                            OutputFormat::Table => {
+                                Table::new(containers.iter().map(Instance::from))
+                            }
+                            OutputFormat::Wide => {
+                                Table::new(containers.iter().map(WideInstance::from))
+                            }
+                        }
+                        .with(Style::pseudo())
+                        .to_string();
+
+                        if atty::is(atty::Stream::Stdout) {
+                            print!("{}", colorize_rows(&table, &containers))
+                        } else {
+                            print!("{}", table)
+                        }
+                    }
+                    Err(e) => fail("Could not list instances", e),
+                },
             }
         }
-        Topics::Util(t) => {
-            let instances = Instances {
-                docker: Docker::new(),
+        Topics::Cycle(t) => {
+            let instances = connect(opts.node.as_ref()).await;
+
+            let (action, selector) = match t.subcmd {
+                LifecycleCommands::Start(c) => (
+                    LifecycleAction::Start,
+                    Selector {
+                        all: c.all,
+                        patterns: c.names,
+                        labels: c.selector,
+                    },
+                ),
+                LifecycleCommands::Stop(c) => (
+                    LifecycleAction::Stop,
+                    Selector {
+                        all: c.all,
+                        patterns: c.names,
+                        labels: c.selector,
+                    },
+                ),
+                LifecycleCommands::Restart(c) => (
+                    LifecycleAction::Restart,
+                    Selector {
+                        all: c.all,
+                        patterns: c.names,
+                        labels: c.selector,
+                    },
+                ),
             };
 
+            let names = resolve(&instances, &selector).await;
+
+            let (progress, past) = match action {
+                LifecycleAction::Start => ("Starting", "Started"),
+                LifecycleAction::Stop => ("Stopping", "Stopped"),
+                LifecycleAction::Restart => ("Restarting", "Restarted"),
+            };
+
+            let sp = spin(opts.output, format!("{} {:?} ...", progress, names));
+
+            let res = instances.run_all(&names, action).await;
+
+            stop_spinner(sp);
+
+            let results = res
+                .iter()
+                .map(|(name, res)| OperationResult::new(name, &action.to_string(), res))
+                .collect::<Vec<_>>();
+
+            if opts.output.is_machine_readable() {
+                report(opts.output, &results, "");
+            } else {
+                print!(
+                    "{}",
+                    Table::new(res.iter().map(|(name, res)| OperationRow {
+                        name: name.to_owned(),
+                        result: match res {
+                            Ok(_) => past.to_lowercase(),
+                            Err(e) => format!("failed: {}", e),
+                        },
+                    }))
+                    .with(Style::pseudo())
+                );
+            }
+
+            exit_on_failures(&res);
+        }
+        Topics::Util(t) => {
+            let instances = connect(opts.node.as_ref()).await;
+
             match t.subcmd {
                 UtilityCommands::Logs(c) => {
-                    let mut logs = instances.get_logs(&c.name).await;
+                    let query = LogQuery {
+                        follow: c.follow,
+                        tail: c.tail,
+                        since: c.since,
+                        until: c.until,
+                        timestamps: c.timestamps,
+                    };
+
+                    let mut logs = instances.get_logs(&c.name, &query).await;
 
                     while let Some(log) = logs.next().await {
-                        match log {
-                            Ok(chunk) => match chunk {
-                                shiplift::tty::TtyChunk::StdOut(b) => {
-                                    print!("{}", from_utf8(&b).unwrap())
-                                }
-                                shiplift::tty::TtyChunk::StdErr(b) => {
-                                    print!("{}", from_utf8(&b).unwrap())
-                                }
-                                shiplift::tty::TtyChunk::StdIn(_) => unreachable!(),
-                            },
-                            Err(e) => eprintln!("Could not get logs: {}", e),
+                        let res = match log {
+                            Ok(Chunk::StdOut(b)) => stdout().write_all(&b),
+                            Ok(Chunk::StdErr(b)) => stderr().write_all(&b),
+                            Err(e) => fail("Could not get logs", e),
+                        };
+
+                        // The reader has gone away, i.e. `head` has exited
+                        if res.is_err() {
+                            return;
                         }
                     }
                 }
                 UtilityCommands::Enter(c) => {
-                    let mut console = instances.enter(&c.name).await;
+                    let cmd = if c.cmd.is_empty() { None } else { Some(c.cmd) };
 
-                    while let Some(log) = console.next().await {
-                        match log {
-                            Ok(chunk) => match chunk {
-                                shiplift::tty::TtyChunk::StdOut(b) => {
-                                    print!("{}", from_utf8(&b).unwrap())
-                                }
-                                shiplift::tty::TtyChunk::StdErr(b) => {
-                                    print!("{}", from_utf8(&b).unwrap())
-                                }
-                                shiplift::tty::TtyChunk::StdIn(_) => unreachable!(),
+                    let res = match instances.enter(&c.name, cmd).await {
+                        Ok(session) => attach(&instances, session).await,
+                        Err(e) => Err(e),
+                    };
+
+                    match res {
+                        // The blocking stdin reader would keep the runtime from shutting down
+                        Ok(_) => exit(0),
+                        Err(e) => fail("Could not enter instance", e),
+                    }
+                }
+                UtilityCommands::Services(c) => {
+                    let services = match instances.services(&c.name).await {
+                        Ok(services) => services,
+                        Err(e) => fail("Could not get services", e),
+                    };
+
+                    if opts.output.is_machine_readable() {
+                        match serialize(opts.output, &services) {
+                            Ok(s) => print!("{}", s),
+                            Err(e) => fail("Could not serialize services", e),
+                        }
+                    } else {
+                        print!(
+                            "{}",
+                            Table::new(services.into_iter().map(|s| ServiceRow {
+                                title: s.title,
+                                port: s.port,
+                                url: s.url,
+                            }))
+                            .with(Style::pseudo())
+                        );
+                    }
+                }
+                UtilityCommands::Open(c) => {
+                    let service = match instances.services(&c.name).await {
+                        Ok(services) => services.into_iter().find(|s| s.id == c.service),
+                        Err(e) => fail("Could not get services", e),
+                    };
+
+                    let url = match service {
+                        Some(s) => s.url,
+                        None => fail(
+                            &format!("Could not open {:?}", c.service),
+                            Error::Other {
+                                name: c.name.to_owned(),
+                                cause: format!(
+                                    "{} isn't published by {:?}, is it running?",
+                                    c.service, c.name
+                                )
+                                .into(),
                             },
-                            Err(e) => eprintln!("Could not enter instance: {}", e),
+                        ),
+                    };
+
+                    match open_url(&url).await {
+                        Ok(_) => report(
+                            opts.output,
+                            &[OperationResult::new(&c.name, "open", &Ok(()))],
+                            &format!("Opened {}.", url),
+                        ),
+                        Err(e) => fail(&format!("Could not open {}", url), e.with_name(&c.name)),
+                    }
+                }
+                UtilityCommands::Events(_) => {
+                    let mut events = instances.watch();
+
+                    while let Some(event) = events.next().await {
+                        let event = match event {
+                            Ok(event) => event,
+                            Err(e) => fail("Could not watch instances", e),
+                        };
+
+                        let line = if opts.output.is_machine_readable() {
+                            match serialize_item(opts.output, &EventOutput::from(&event)) {
+                                Ok(s) => s,
+                                Err(e) => fail("Could not serialize event", e),
+                            }
+                        } else {
+                            format!(
+                                "{} {} {}\n",
+                                event.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                                event.name,
+                                event.kind
+                            )
+                        };
+
+                        let mut out = stdout();
+                        // The reader has gone away, i.e. `head` has exited
+                        if out
+                            .write_all(line.as_bytes())
+                            .and_then(|_| out.flush())
+                            .is_err()
+                        {
+                            return;
                         }
                     }
                 }
-                UtilityCommands::Forward(_) => todo!(),
+                UtilityCommands::Forward(c) => {
+                    c.address.iter().for_each(|spec| {
+                        println!("Forwarding {} ({}) for {:?}", spec, c.direction, c.name)
+                    });
+                    println!("Press Ctrl-C to stop.");
+
+                    let shutdown = async {
+                        tokio::signal::ctrl_c().await.ok();
+                    };
+
+                    let on_error = |spec: &ForwardSpec, e: Error| {
+                        eprintln!("Could not forward connection for {}: {}", spec, e)
+                    };
+
+                    match forward(
+                        &instances,
+                        &c.name,
+                        &c.address,
+                        c.direction,
+                        shutdown,
+                        &on_error,
+                    )
+                    .await
+                    {
+                        Ok(_) => println!("Stopped forwarding."),
+                        Err(e) => fail("Could not forward", e),
+                    }
+                }
             }
         }
         Topics::Misc(t) => match t.subcmd {
@@ -459,8 +1291,120 @@ pub async fn main() {
                     Err(e) => eprintln!("Could not update: {}", e),
                 }
             }
-            MiscellaneousCommands::GetCACert(_) => todo!(),
-            MiscellaneousCommands::ResetCA(_) => todo!(),
+            MiscellaneousCommands::GetCACert(c) => {
+                let instances = connect(opts.node.as_ref()).await;
+
+                let cert = match instances.get_ca_cert().await {
+                    Ok(cert) => cert,
+                    Err(e) => fail("Could not get CA certificate", e),
+                };
+
+                if c.print {
+                    print!("{}", cert);
+                } else {
+                    match fs::write(CA_CERT_FILE, cert) {
+                        Ok(_) => report(
+                            opts.output,
+                            &[OperationResult::new(CA_CERT_FILE, "get-ca-cert", &Ok(()))],
+                            &format!(
+                                "Saved CA certificate to {:?}, import it into your browser to trust all instances.",
+                                CA_CERT_FILE
+                            ),
+                        ),
+                        Err(e) => fail(
+                            "Could not save CA certificate",
+                            Error::Other {
+                                name: CA_CERT_FILE.to_owned(),
+                                cause: e.into(),
+                            },
+                        ),
+                    }
+                }
+            }
+            MiscellaneousCommands::ResetCA(c) => {
+                let instances = connect(opts.node.as_ref()).await;
+
+                if !c.force
+                    && !confirm("Resetting the CA will invalidate the current certificate and restart all running instances. Continue?")
+                {
+                    return;
+                }
+
+                let sp = spin(opts.output, "Resetting CA ...".to_owned());
+
+                let res = instances.reset_ca().await;
+
+                stop_spinner(sp);
+
+                match res {
+                    Ok(_) => report(
+                        opts.output,
+                        &[OperationResult::new("ca", "reset-ca", &Ok(()))],
+                        "Reset CA, please import the new certificate from `misc get-ca-cert` into your browser.",
+                    ),
+                    Err(e) => fail("Could not reset CA", e),
+                }
+            }
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // clap only checks the flags of a subcommand once it is parsed, i.e. for clashing short flags
+    fn parse(args: &[&str]) -> Opts {
+        match Opts::try_parse_from(std::iter::once("pojdectl-rs").chain(args.iter().copied())) {
+            Ok(opts) => opts,
+            Err(e) => panic!("could not parse {:?}: {}", args, e),
+        }
+    }
+
+    #[test]
+    fn logs_accept_tail_and_node() {
+        let opts = parse(&["util", "logs", "test", "--tail", "10", "-n", "me@host"]);
+
+        assert_eq!(opts.node.map(|n| n.host), Some("host".to_owned()));
+        match opts.subcmd {
+            Topics::Util(Util {
+                subcmd: UtilityCommands::Logs(c),
+            }) => assert_eq!(c.tail, Some(10)),
+            _ => panic!("expected the logs command"),
+        }
+    }
+
+    #[test]
+    fn subcommands_parse() {
+        for args in [
+            &[
+                "modify", "apply", "test", "8000", "-n", "host", "-o", "json",
+            ][..],
+            &[
+                "modify",
+                "apply",
+                "-f",
+                "pojde.yaml",
+                "--prune",
+                "--dry-run",
+            ],
+            &["modify", "remove", "test", "--all", "--force"],
+            &["modify", "list", "--wide"],
+            &["cycle", "start", "-l", "team=a"],
+            &["cycle", "stop", "test"],
+            &["cycle", "restart", "test"],
+            &["util", "logs", "test", "-f", "-t"],
+            &["util", "enter", "test"],
+            &["util", "forward", "test", "8080:80"],
+            &["util", "events"],
+            &["util", "services", "test"],
+            &["util", "open", "test", "theia"],
+            &["misc", "upgrade-pojdectl"],
+            &["misc", "reset-ca", "-f"],
+        ]
+        .iter()
+        {
+            parse(args);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::{error, fmt};
+
+pub type Cause = Box<dyn error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound { name: String, cause: Cause },
+    DaemonUnreachable { name: String, cause: Cause },
+    PermissionDenied { name: String, cause: Cause },
+    PortAllocated { name: String, cause: Cause },
+    ImageMissing { name: String, cause: Cause },
+    Conflict { name: String, cause: Cause },
+    Other { name: String, cause: Cause },
+}
+
+impl Error {
+    pub fn name(self: &Self) -> &str {
+        match self {
+            Self::NotFound { name, .. }
+            | Self::DaemonUnreachable { name, .. }
+            | Self::PermissionDenied { name, .. }
+            | Self::PortAllocated { name, .. }
+            | Self::ImageMissing { name, .. }
+            | Self::Conflict { name, .. }
+            | Self::Other { name, .. } => name,
+        }
+    }
+
+    pub fn with_name(self: Self, name: &str) -> Self {
+        let name = name.to_owned();
+
+        match self {
+            Self::NotFound { cause, .. } => Self::NotFound { name, cause },
+            Self::DaemonUnreachable { cause, .. } => Self::DaemonUnreachable { name, cause },
+            Self::PermissionDenied { cause, .. } => Self::PermissionDenied { name, cause },
+            Self::PortAllocated { cause, .. } => Self::PortAllocated { name, cause },
+            Self::ImageMissing { cause, .. } => Self::ImageMissing { name, cause },
+            Self::Conflict { cause, .. } => Self::Conflict { name, cause },
+            Self::Other { cause, .. } => Self::Other { name, cause },
+        }
+    }
+
+    pub fn cause(self: &Self) -> &Cause {
+        match self {
+            Self::NotFound { cause, .. }
+            | Self::DaemonUnreachable { cause, .. }
+            | Self::PermissionDenied { cause, .. }
+            | Self::PortAllocated { cause, .. }
+            | Self::ImageMissing { cause, .. }
+            | Self::Conflict { cause, .. }
+            | Self::Other { cause, .. } => cause,
+        }
+    }
+
+    pub fn exit_code(self: &Self) -> i32 {
+        match self {
+            Self::Other { .. } => 1,
+            Self::NotFound { .. } => 3,
+            Self::DaemonUnreachable { .. } => 4,
+            Self::PermissionDenied { .. } => 5,
+            Self::PortAllocated { .. } => 6,
+            Self::ImageMissing { .. } => 7,
+            Self::Conflict { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { name, .. } => write!(f, "instance {:?} does not exist", name),
+            Self::DaemonUnreachable { .. } => write!(
+                f,
+                "could not reach the Docker daemon, is it running and is `DOCKER_HOST` set correctly?"
+            ),
+            Self::PermissionDenied { .. } => write!(
+                f,
+                "permission denied while connecting to the Docker socket, is your user in the `docker` group?"
+            ),
+            Self::PortAllocated { name, .. } => write!(
+                f,
+                "a port of instance {:?} is already allocated, please choose another start port",
+                name
+            ),
+            Self::ImageMissing { name, .. } => write!(
+                f,
+                "the image for instance {:?} is missing, please apply it with `--upgrade` to pull it",
+                name
+            ),
+            Self::Conflict { name, .. } => write!(
+                f,
+                "instance {:?} is in a conflicting state, please retry or re-create it",
+                name
+            ),
+            Self::Other { .. } => write!(f, "{}", self.cause()),
+        }?;
+
+        match self {
+            Self::Other { .. } => Ok(()),
+            _ => write!(f, " ({})", self.cause()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.cause().as_ref())
+    }
+}
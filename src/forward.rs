@@ -0,0 +1,349 @@
+use std::{fmt, future::Future, str::FromStr, sync::Mutex};
+
+use futures::{future::try_join_all, stream::FuturesUnordered, FutureExt, StreamExt};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    backend::{Chunk, ExecSession},
+    error::Cause,
+    instances::Instances,
+    Error,
+};
+
+static SOCAT: &str = "socat";
+static ACCEPTED_MARKER: &str = "accepting connection";
+// Listeners which wait in the instance per spec, so that browsers can open several connections at once
+static LISTENERS: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    // Connections to the host are proxied into the instance
+    Local,
+    // Connections to the instance are proxied to the host
+    Remote,
+}
+
+impl FromStr for Direction {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Self::Local),
+            "remote" => Ok(Self::Remote),
+            _ => Err("no match"),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "local"),
+            Self::Remote => write!(f, "remote"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForwardSpec {
+    pub local_host: String,
+    pub local_port: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+impl FromStr for ForwardSpec {
+    type Err = String;
+
+    // Parses `local host:local port:remote host:remote port`, or just the ports for `localhost`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (local_host, local_port, remote_host, remote_port) =
+            match s.split(':').collect::<Vec<_>>().as_slice() {
+                [local_host, local_port, remote_host, remote_port] => {
+                    (*local_host, *local_port, *remote_host, *remote_port)
+                }
+                [local_port, remote_port] => ("localhost", *local_port, "localhost", *remote_port),
+                _ => return Err(format!(
+                    "invalid address {:?}, expected local host:local port:remote host:remote port",
+                    s
+                )),
+            };
+
+        let parse_port = |port: &str| {
+            port.parse::<u16>()
+                .map_err(|_| format!("invalid port {:?}", port))
+        };
+
+        Ok(Self {
+            local_host: local_host.to_owned(),
+            local_port: parse_port(local_port)?,
+            remote_host: remote_host.to_owned(),
+            remote_port: parse_port(remote_port)?,
+        })
+    }
+}
+
+impl fmt::Display for ForwardSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.local_host, self.local_port, self.remote_host, self.remote_port
+        )
+    }
+}
+
+impl ForwardSpec {
+    fn connect_cmd(self: &Self) -> Vec<String> {
+        vec![
+            SOCAT.to_owned(),
+            "-".to_owned(),
+            format!("TCP:{}:{}", self.remote_host, self.remote_port),
+        ]
+    }
+
+    // Without `fork`, `socat` stops listening after the first connection, so there is one listener per connection;
+    // they share the port through `reuseport` and print their PID first so that they can be stopped
+    fn listen_cmd(self: &Self) -> Vec<String> {
+        vec![
+            "sh".to_owned(),
+            "-c".to_owned(),
+            r#"echo $$ && exec "$@""#.to_owned(),
+            "sh".to_owned(),
+            SOCAT.to_owned(),
+            "-d".to_owned(),
+            "-d".to_owned(),
+            format!(
+                "TCP-LISTEN:{},bind={},reuseaddr,reuseport",
+                self.remote_port, self.remote_host
+            ),
+            "STDIO".to_owned(),
+        ]
+    }
+}
+
+fn other(name: &str, cause: impl Into<Cause>) -> Error {
+    Error::Other {
+        name: name.to_owned(),
+        cause: cause.into(),
+    }
+}
+
+// Failed connections are passed to `on_error` and don't stop forwarding
+pub type ErrorHandler<'a> = &'a (dyn Fn(&ForwardSpec, Error) + Sync);
+
+// Forwards all specs concurrently until one of them can't listen anymore or `shutdown` resolves
+pub async fn forward(
+    instances: &Instances,
+    name: &str,
+    specs: &[ForwardSpec],
+    direction: Direction,
+    shutdown: impl Future<Output = ()>,
+    on_error: ErrorHandler<'_>,
+) -> Result<(), Error> {
+    // Otherwise a typo would only show up once the first connection arrives
+    if !instances.exists(name).await? {
+        return Err(Error::NotFound {
+            name: name.to_owned(),
+            cause: "no such instance".into(),
+        });
+    }
+
+    // PIDs of the listeners which are still waiting for a connection
+    let armed = Mutex::new(vec![]);
+
+    let forwards = specs
+        .iter()
+        .map(|spec| match direction {
+            Direction::Local => forward_local(instances, name, spec, on_error).boxed(),
+            Direction::Remote => forward_remote(instances, name, spec, &armed, on_error).boxed(),
+        })
+        .collect::<Vec<_>>();
+
+    let res = tokio::select! {
+        res = try_join_all(forwards) => res.map(|_| ()),
+        _ = shutdown => Ok(()),
+    };
+
+    // Listeners only notice that their session is gone once they accept a connection
+    let pids = armed.lock().unwrap().clone();
+    if !pids.is_empty() {
+        stop_listeners(instances, name, &pids).await.ok();
+    }
+
+    res
+}
+
+async fn forward_local(
+    instances: &Instances,
+    name: &str,
+    spec: &ForwardSpec,
+    on_error: ErrorHandler<'_>,
+) -> Result<(), Error> {
+    let listener = TcpListener::bind((spec.local_host.as_str(), spec.local_port))
+        .await
+        .map_err(|e| other(name, e))?;
+
+    let mut connections = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted.map_err(|e| other(name, e))?;
+
+                connections.push(async move {
+                    let session = instances.exec(name, spec.connect_cmd(), false).await?;
+
+                    pipe(name, session, stream).await
+                });
+            }
+            Some(res) = connections.next() => if let Err(e) = res {
+                on_error(spec, e);
+            },
+        }
+    }
+}
+
+async fn forward_remote(
+    instances: &Instances,
+    name: &str,
+    spec: &ForwardSpec,
+    armed: &Mutex<Vec<String>>,
+    on_error: ErrorHandler<'_>,
+) -> Result<(), Error> {
+    let mut listeners = (0..LISTENERS)
+        .map(|_| listen(instances, name, spec, armed))
+        .collect::<FuturesUnordered<_>>();
+    let mut connections = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            Some(accepted) = listeners.next() => {
+                let session = accepted?;
+
+                // The other listeners keep accepting while this one is replaced
+                listeners.push(listen(instances, name, spec, armed));
+
+                connections.push(async move {
+                    // Refused connections only close the instance's side, just like `ssh -R` does
+                    match TcpStream::connect((spec.local_host.as_str(), spec.local_port)).await {
+                        Ok(stream) => pipe(name, session, stream).await,
+                        Err(_) => Ok(()),
+                    }
+                });
+            }
+            Some(res) = connections.next() => if let Err(e) = res {
+                on_error(spec, e);
+            },
+        }
+    }
+}
+
+// Starts a listener in the instance and returns its session once it has accepted a connection;
+// `socat -d -d` logs accepted connections to stderr
+async fn listen(
+    instances: &Instances,
+    name: &str,
+    spec: &ForwardSpec,
+    armed: &Mutex<Vec<String>>,
+) -> Result<ExecSession, Error> {
+    let mut session = instances.exec(name, spec.listen_cmd(), false).await?;
+    let mut pid = None;
+    let mut stdout = String::new();
+    let mut log = String::new();
+
+    let accepted = async {
+        while let Some(chunk) = session.output.next().await {
+            match chunk? {
+                Chunk::StdOut(b) => stdout.push_str(&String::from_utf8_lossy(&b)),
+                Chunk::StdErr(b) => log.push_str(&String::from_utf8_lossy(&b)),
+            }
+
+            if pid.is_none() {
+                if let Some((line, _)) = stdout.split_once('\n') {
+                    let line = line.trim().to_owned();
+
+                    armed.lock().unwrap().push(line.clone());
+                    pid = Some(line);
+                }
+            }
+
+            if log.contains(ACCEPTED_MARKER) {
+                return Ok(());
+            }
+        }
+
+        Err(other(
+            name,
+            format!("could not listen in instance: {}", log.trim()),
+        ))
+    }
+    .await;
+
+    // Listeners which are dropped before accepting stay armed, so that `forward` stops them
+    if let Some(pid) = pid {
+        armed.lock().unwrap().retain(|p| *p != pid);
+    }
+
+    accepted.map(|_| session)
+}
+
+// Stops the given listeners, but not those of other processes which forward the same port
+async fn stop_listeners(instances: &Instances, name: &str, pids: &[String]) -> Result<(), Error> {
+    let mut cmd = vec!["kill".to_owned()];
+    cmd.extend(pids.iter().cloned());
+
+    let mut session = instances.exec(name, cmd, false).await?;
+    session.input.shutdown().await.map_err(|e| other(name, e))?;
+
+    while let Some(chunk) = session.output.next().await {
+        chunk?;
+    }
+
+    Ok(())
+}
+
+// Copies between a connection and the stdio of a session until the session's output ends
+async fn pipe(name: &str, session: ExecSession, stream: TcpStream) -> Result<(), Error> {
+    let ExecSession {
+        mut output,
+        mut input,
+        ..
+    } = session;
+    let (mut reader, mut writer) = stream.into_split();
+
+    let upstream = async {
+        tokio::io::copy(&mut reader, &mut input).await?;
+
+        input.shutdown().await
+    };
+
+    let downstream = async {
+        while let Some(chunk) = output.next().await {
+            // `socat`'s own diagnostics are not part of the connection
+            if let Chunk::StdOut(b) = chunk? {
+                writer.write_all(&b).await.map_err(|e| other(name, e))?;
+            }
+        }
+
+        writer.shutdown().await.map_err(|e| other(name, e))
+    };
+
+    tokio::pin!(upstream, downstream);
+
+    let mut upstream_closed = false;
+
+    loop {
+        tokio::select! {
+            // The peer may still expect a response after closing its side
+            res = &mut upstream, if !upstream_closed => match res {
+                Ok(_) => upstream_closed = true,
+                Err(_) => return Ok(()),
+            },
+            res = &mut downstream => return res,
+        }
+    }
+}
@@ -1,94 +1,1436 @@
-use futures::Stream;
-use shiplift::{
-    tty, ContainerFilter, ContainerListOptions, Docker, Error, ExecContainerOptions, LogsOptions,
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::{
+    future::{self, join_all, try_join_all},
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use glob::Pattern;
+use tokio::sync::Mutex;
+
+use crate::{
+    backend::{
+        Chunk, ContainerBackend, ContainerEvent, ContainerSpec, ContainerSummary, DockerBackend,
+        Endpoint, ExecSession, LogQuery, PullProgress,
+    },
+    ca::CertificateAuthority,
+    error::Error,
+    node::Node,
+    plan::{Operation, PlannedChange, Resource},
+    ports::{PortAllocator, Reservation, DEFAULT_START_PORT},
 };
 
 static POJDE_PREFIX: &str = "pojde-";
+static POJDE_IMAGE: &str = "pojntfx/pojde";
+static POJDE_TAG: &str = "latest";
+// Records the `InstanceConfig` a container was created with
+static CONFIG_LABEL: &str = "io.pojde.config";
+static DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+// Container ports of the services, published in this order starting at `start_port`
+static POJDE_PORTS: [u32; 7] = [8000, 8001, 8002, 8003, 8004, 8005, 22];
+
+// IDs, titles, container ports and URL schemes of the services
+pub static POJDE_SERVICES: [(&str, &str, u32, &str); 7] = [
+    ("cockpit", "Cockpit", 8000, "https"),
+    ("code-server", "code-server", 8001, "https"),
+    ("theia", "Theia", 8002, "https"),
+    ("jupyter-lab", "JupyterLab", 8003, "https"),
+    ("ttyd", "ttyd", 8004, "https"),
+    ("novnc", "noVNC", 8005, "https"),
+    ("ssh", "SSH", 22, "ssh"),
+];
+
+// Starts a login shell for the instance's user (the first regular user), falling back to root
+static LOGIN_SHELL: &str = r#"user="$(getent passwd 1000 | cut -d: -f1)"; if [ -n "$user" ]; then exec su - "$user"; fi; exec "$(getent passwd root | cut -d: -f7)" -l"#;
+
+// Volume suffixes and their mount points in the container
+static POJDE_VOLUMES: [(&str, &str); 6] = [
+    ("preferences", "/opt/pojde/preferences"),
+    ("configuration", "/opt/pojde/configuration"),
+    ("home-root", "/root"),
+    ("home-user", "/home"),
+    ("transfer", "/opt/pojde/transfer"),
+    ("apt-cache", "/var/cache/apt/archives"),
+];
+static POJDE_CA_VOLUME: (&str, &str) = ("pojde-ca", "/opt/pojde/ca");
+
+// Language modules which the instance installs on startup, as IDs and display names
+pub static POJDE_MODULES: [(&str, &str); 10] = [
+    ("cpp", "C/C++"),
+    ("go", "Go"),
+    ("js", "JavaScript/TypeScript"),
+    ("python", "Python"),
+    ("java", "Java"),
+    ("csharp", "C#"),
+    ("rust", "Rust"),
+    ("ruby", "Ruby"),
+    ("r", "R"),
+    ("tex", "LaTeX"),
+];
+// Sourced by the instance's setup, which installs the listed modules
+static POJDE_MODULES_FILE: &str = "/opt/pojde/preferences/modules.sh";
+
+// Stopped container which mounts the CA volume so that its files can be accessed
+static CA_HELPER: &str = "pojdectl-ca";
+static CA_CERT_FILE: &str = "ca.pem";
+static CA_KEY_FILE: &str = "ca.key";
 
 pub struct Instances {
-    pub docker: Docker,
+    backend: Box<dyn ContainerBackend>,
+    // Host on which the ports of the instances are published
+    host: String,
+    // Serializes access to the CA volume, which is shared by all instances
+    ca_lock: Mutex<()>,
+    // Listeners can only be probed if the daemon runs on this host
+    check_host_ports: bool,
 }
 
 pub struct Instance {
     pub name: String,
     pub start_port: Option<u64>,
     pub end_port: Option<u64>,
-    pub status: String,
+    pub status: InstanceStatus,
+    pub health: Option<InstanceHealth>,
+    pub uptime: Option<Duration>,
+    pub image: String,
+    pub created: DateTime<Utc>,
+    pub volumes: Vec<String>,
+    // Missing for containers which were created by older versions
+    pub config: Option<InstanceConfig>,
+    pub host: String,
+    // Pairs of container port and host port
+    pub published: Vec<(u64, u64)>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Service {
+    pub id: String,
+    pub title: String,
+    pub port: u64,
+    pub url: String,
+}
+
+impl Instance {
+    // Stopped instances don't publish ports, so their services are derived from their configuration
+    pub fn services(self: &Self) -> Vec<Service> {
+        POJDE_SERVICES
+            .iter()
+            .filter_map(|(id, title, container_port, scheme)| {
+                let port = self
+                    .published
+                    .iter()
+                    .find(|(c, _)| *c == *container_port as u64)
+                    .map(|(_, host_port)| *host_port)
+                    .or_else(|| match &self.config {
+                        Some(config) if self.published.is_empty() => {
+                            let offset = POJDE_PORTS.iter().position(|p| p == container_port)?;
+
+                            Some(config.start_port.saturating_add(offset as u64))
+                        }
+                        _ => None,
+                    })?;
+
+                Some(Service {
+                    id: id.to_string(),
+                    title: title.to_string(),
+                    port,
+                    url: format!("{}://{}:{}", scheme, self.host, port),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+pub enum InstanceStatus {
+    Created,
+    Running,
+    Paused,
+    Restarting,
+    Exited(u64),
+    Dead,
+    Removing,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+pub enum InstanceHealth {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum InstanceEventKind {
+    Created,
+    Started,
+    // The exit code, if the daemon reported one
+    Died(Option<u64>),
+    Removed,
+    HealthChanged(InstanceHealth),
+    VolumeCreated(String),
+    VolumeRemoved(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct InstanceEvent {
+    pub name: String,
+    pub kind: InstanceEventKind,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LifecycleAction {
+    Start,
+    Stop,
+    Restart,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selector {
+    pub all: bool,
+    // Literal names or shell globs, i.e. `team-*`
+    pub patterns: Vec<String>,
+    // Labels which selected instances must all have
+    pub labels: Vec<Label>,
+}
+
+#[derive(Clone, Default)]
+pub struct RemoveScope {
+    pub customizations: bool,
+    pub preferences: bool,
+    pub security: bool,
+    pub user_data: bool,
+    pub transfer: bool,
+    pub deb_cache: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct ApplyOptions {
+    pub start_port: u64,
+    pub upgrade: bool,
+    pub recreate: bool,
+    pub isolate: bool,
+    pub privileged: bool,
+    // IDs of language modules; the previous selection is kept if empty
+    pub modules: Vec<String>,
+    pub resources: Resources,
+    // Additional bind mounts, i.e. `/home/me/src:/src`
+    pub volumes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Resources {
+    // Number of CPUs, i.e. 1.5
+    pub cpus: Option<f64>,
+    // In bytes
+    pub memory: Option<u64>,
+}
+
+// Options which can only be changed by re-creating the container
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct InstanceConfig {
+    pub start_port: u64,
+    pub isolate: bool,
+    pub privileged: bool,
+    pub resources: Resources,
+    pub volumes: Vec<String>,
+}
+
+impl ApplyOptions {
+    pub fn config(self: &Self) -> InstanceConfig {
+        InstanceConfig {
+            start_port: self.start_port,
+            isolate: self.isolate,
+            privileged: self.privileged,
+            resources: self.resources.clone(),
+            volumes: self.volumes.clone(),
+        }
+    }
+}
+
+impl InstanceStatus {
+    fn from_state(state: &str, exit_code: u64) -> Self {
+        match state {
+            "created" => Self::Created,
+            "running" => Self::Running,
+            "paused" => Self::Paused,
+            "restarting" => Self::Restarting,
+            "exited" => Self::Exited(exit_code),
+            "removing" => Self::Removing,
+            _ => Self::Dead,
+        }
+    }
+
+    pub fn can_start(self: &Self) -> bool {
+        matches!(self, Self::Created | Self::Exited(_))
+    }
+
+    pub fn can_stop(self: &Self) -> bool {
+        matches!(self, Self::Running | Self::Paused | Self::Restarting)
+    }
+
+    pub fn can_restart(self: &Self) -> bool {
+        matches!(self, Self::Running | Self::Exited(_))
+    }
+}
+
+impl fmt::Display for InstanceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Created => write!(f, "created"),
+            Self::Running => write!(f, "running"),
+            Self::Paused => write!(f, "paused"),
+            Self::Restarting => write!(f, "restarting"),
+            Self::Exited(code) => write!(f, "exited ({})", code),
+            Self::Dead => write!(f, "dead"),
+            Self::Removing => write!(f, "removing"),
+        }
+    }
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(Self {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            }),
+            _ => Err(format!("invalid label {:?}, expected key=value", s)),
+        }
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+impl Selector {
+    pub fn names(names: &[String]) -> Self {
+        Self {
+            patterns: names.to_vec(),
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = self.patterns.clone();
+        if self.all {
+            parts.push("all instances".to_owned());
+        }
+        parts.extend(self.labels.iter().map(|l| l.to_string()));
+
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(|c| matches!(c, '*' | '?' | '['))
+}
+
+impl fmt::Display for LifecycleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Start => write!(f, "start"),
+            Self::Stop => write!(f, "stop"),
+            Self::Restart => write!(f, "restart"),
+        }
+    }
+}
+
+impl InstanceHealth {
+    // shiplift's `State` lacks Docker's `State.Health`, so it is parsed from the human-readable status, i.e. `Up 2 hours (healthy)`
+    fn from_status(status: &str) -> Option<Self> {
+        if status.ends_with("(health: starting)") {
+            Some(Self::Starting)
+        } else if status.ends_with("(unhealthy)") {
+            Some(Self::Unhealthy)
+        } else if status.ends_with("(healthy)") {
+            Some(Self::Healthy)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for InstanceEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Created => write!(f, "created"),
+            Self::Started => write!(f, "started"),
+            Self::Died(Some(code)) => write!(f, "died ({})", code),
+            Self::Died(None) => write!(f, "died"),
+            Self::Removed => write!(f, "removed"),
+            Self::HealthChanged(health) => write!(f, "health changed to {}", health),
+            Self::VolumeCreated(volume) => write!(f, "volume {} created", volume),
+            Self::VolumeRemoved(volume) => write!(f, "volume {} removed", volume),
+        }
+    }
+}
+
+impl InstanceEvent {
+    // Events of other containers and of the shared CA volume are dropped
+    fn from_container_event(event: ContainerEvent) -> Option<Self> {
+        let (name, kind) = match event.kind.as_str() {
+            "container" => {
+                let name = event.attributes.get("name")?.strip_prefix(POJDE_PREFIX)?;
+
+                let kind = match event.action.as_str() {
+                    "create" => InstanceEventKind::Created,
+                    "start" => InstanceEventKind::Started,
+                    "die" => InstanceEventKind::Died(
+                        event
+                            .attributes
+                            .get("exitCode")
+                            .and_then(|c| c.parse().ok()),
+                    ),
+                    "destroy" => InstanceEventKind::Removed,
+                    "health_status: starting" => {
+                        InstanceEventKind::HealthChanged(InstanceHealth::Starting)
+                    }
+                    "health_status: healthy" => {
+                        InstanceEventKind::HealthChanged(InstanceHealth::Healthy)
+                    }
+                    "health_status: unhealthy" => {
+                        InstanceEventKind::HealthChanged(InstanceHealth::Unhealthy)
+                    }
+                    _ => return None,
+                };
+
+                (name.to_owned(), kind)
+            }
+            "volume" => {
+                let rest = event.id.strip_prefix(POJDE_PREFIX)?;
+                let name = POJDE_VOLUMES
+                    .iter()
+                    .find_map(|(suffix, _)| rest.strip_suffix(suffix)?.strip_suffix('-'))?;
+
+                let kind = match event.action.as_str() {
+                    "create" => InstanceEventKind::VolumeCreated(event.id.to_owned()),
+                    "destroy" => InstanceEventKind::VolumeRemoved(event.id.to_owned()),
+                    _ => return None,
+                };
+
+                (name.to_owned(), kind)
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            name,
+            kind,
+            time: event.time,
+        })
+    }
+}
+
+impl fmt::Display for InstanceHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Starting => write!(f, "starting"),
+            Self::Healthy => write!(f, "healthy"),
+            Self::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+fn ca_path(file: &str) -> String {
+    format!("{}/{}", POJDE_CA_VOLUME.1, file)
+}
+
+pub fn container_name(name: &str) -> String {
+    POJDE_PREFIX.to_owned() + name
+}
+
+// Instance names become part of container and volume names, so they follow Docker's rules
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = chars.next().map_or(false, |c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid name {:?}, expected letters, digits, _, . or - starting with a letter or digit",
+            name
+        ))
+    }
+}
+
+// The first and last host port which an instance publishes; see `validate_start_port` for the valid range
+pub fn port_range(start_port: u64) -> (u64, u64) {
+    (
+        start_port,
+        start_port.saturating_add(POJDE_PORTS.len() as u64 - 1),
+    )
+}
+
+// All ports of the instance have to be valid TCP ports
+pub fn validate_start_port(start_port: u64) -> Result<(), String> {
+    let (start, end) = port_range(start_port);
+
+    if start == 0 || end > u16::MAX as u64 {
+        return Err(format!(
+            "ports {}-{} are out of range, expected 1-{}",
+            start,
+            end,
+            u16::MAX
+        ));
+    }
+
+    Ok(())
+}
+
+// Options of existing instances only change when they are re-created, so different ones would be ignored
+fn check_unchanged(instance: &Instance, options: &ApplyOptions) -> Result<(), Error> {
+    let changed = match &instance.config {
+        Some(config) => *config != options.config(),
+        // Instances created by older versions only record their ports
+        None => instance
+            .start_port
+            .map_or(false, |start_port| start_port != options.start_port),
+    };
+
+    if changed {
+        return Err(Error::Conflict {
+            name: instance.name.to_owned(),
+            cause: "options changed, re-run with --recreate".into(),
+        });
+    }
+
+    Ok(())
+}
+
+impl RemoveScope {
+    pub fn all() -> Self {
+        Self {
+            customizations: true,
+            preferences: true,
+            security: true,
+            user_data: true,
+            transfer: true,
+            deb_cache: true,
+        }
+    }
+
+    pub fn volumes(self: &Self, names: &[String]) -> Vec<String> {
+        let suffixes = [
+            (self.preferences, "preferences"),
+            (self.customizations, "configuration"),
+            (self.user_data, "home-root"),
+            (self.user_data, "home-user"),
+            (self.transfer, "transfer"),
+            (self.deb_cache, "apt-cache"),
+        ];
+
+        let mut volumes = names
+            .iter()
+            .flat_map(|name| {
+                suffixes
+                    .iter()
+                    .filter(|(selected, _)| *selected)
+                    .map(move |(_, suffix)| format!("{}-{}", container_name(name), suffix))
+            })
+            .collect::<Vec<_>>();
+
+        // The CA is shared between all instances
+        if self.security {
+            volumes.push(POJDE_CA_VOLUME.0.to_owned());
+        }
+
+        volumes
+    }
+
+    // The CA can't be removed while `others` instances still mount it
+    pub fn removable_volumes(self: &Self, names: &[String], others: bool) -> Vec<String> {
+        self.volumes(names)
+            .into_iter()
+            .filter(|volume| !others || volume != POJDE_CA_VOLUME.0)
+            .collect()
+    }
+}
+
+impl Default for Instances {
+    fn default() -> Self {
+        Self {
+            // Daemons behind TCP may run on another machine
+            check_host_ports: matches!(Endpoint::from_env(), Endpoint::Unix(_)),
+            ..Self::new(Box::new(DockerBackend::default()))
+        }
+    }
 }
 
 impl Instances {
-    fn get_container(self: &Self, name: &str) -> shiplift::Container<'_> {
-        self.docker.containers().get(POJDE_PREFIX.to_owned() + name)
+    pub fn new(backend: Box<dyn ContainerBackend>) -> Self {
+        Self {
+            backend,
+            host: "localhost".to_owned(),
+            ca_lock: Mutex::new(()),
+            check_host_ports: false,
+        }
+    }
+
+    pub async fn connect(node: Option<&Node>) -> Result<Self, Error> {
+        match node {
+            Some(node) => Ok(Self {
+                host: node.host.to_owned(),
+                ..Self::new(Box::new(DockerBackend::remote(node).await?))
+            }),
+            None => Ok(Self::default()),
+        }
     }
 
-    pub async fn start(self: &Self, name: &str) -> Result<(), shiplift::Error> {
-        self.get_container(name).start().await
+    fn get_image(self: &Self) -> String {
+        POJDE_IMAGE.to_owned() + ":" + POJDE_TAG
     }
 
-    pub async fn stop(self: &Self, name: &str) -> Result<(), shiplift::Error> {
-        self.get_container(name).stop(None).await
+    pub async fn exists(self: &Self, name: &str) -> Result<bool, Error> {
+        match self.backend.inspect(&container_name(name)).await {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e.with_name(name)),
+        }
     }
 
-    pub async fn restart(self: &Self, name: &str) -> Result<(), shiplift::Error> {
-        self.get_container(name).restart(None).await
+    async fn create(self: &Self, name: &str, options: &ApplyOptions) -> Result<(), Error> {
+        let full_name = container_name(name);
+
+        let mut volumes = POJDE_VOLUMES
+            .iter()
+            .map(|(suffix, path)| format!("{}-{}:{}", full_name, suffix, path))
+            .collect::<Vec<_>>();
+        volumes.push(format!("{}:{}", POJDE_CA_VOLUME.0, POJDE_CA_VOLUME.1));
+        if !options.isolate {
+            volumes.push(format!("{}:{}", DOCKER_SOCKET, DOCKER_SOCKET));
+        }
+        volumes.extend(options.volumes.iter().cloned());
+
+        let mut labels = HashMap::new();
+        labels.insert(
+            CONFIG_LABEL.to_owned(),
+            serde_json::to_string(&options.config()).map_err(|e| Error::Other {
+                name: name.to_owned(),
+                cause: e.into(),
+            })?,
+        );
+
+        let ports = POJDE_PORTS
+            .iter()
+            .enumerate()
+            .map(|(i, port)| (*port, (options.start_port + i as u64) as u32))
+            .collect();
+
+        self.backend
+            .create(&ContainerSpec {
+                name: full_name,
+                image: self.get_image(),
+                ports,
+                volumes,
+                privileged: options.privileged,
+                labels,
+                memory: options.resources.memory,
+                cpus: options.resources.cpus,
+            })
+            .await
+            .map_err(|e| e.with_name(name))
     }
 
-    pub async fn get_logs(
+    // Pulls the image if it is missing or if upgrading; the stream is empty otherwise
+    pub fn pull_image(
         self: &Self,
         name: &str,
-    ) -> impl Stream<Item = Result<tty::TtyChunk, Error>> + '_ {
-        self.get_container(name)
-            .logs(&LogsOptions::builder().stdout(true).stderr(true).build())
-    }
-
-    pub async fn get_instances(self: &Self) -> Result<Vec<Instance>, shiplift::Error> {
-        let instances = self
-            .docker
-            .containers()
-            .list(
-                &ContainerListOptions::builder()
-                    .all()
-                    .filter(vec![ContainerFilter::Name("/".to_owned() + POJDE_PREFIX)])
-                    .build(),
+        upgrade: bool,
+    ) -> BoxStream<'_, Result<PullProgress, Error>> {
+        let name = name.to_owned();
+
+        stream::once(async move {
+            let image = self.get_image();
+
+            match self.backend.image_exists(&image).await {
+                Ok(true) if !upgrade => stream::empty().boxed(),
+                Ok(_) => self.backend.pull(&image),
+                Err(e) => stream::once(future::ready(Err(e))).boxed(),
+            }
+        })
+        .flatten()
+        .map(move |p| p.map_err(|e| e.with_name(&name)))
+        .boxed()
+    }
+
+    async fn ensure_image(self: &Self, name: &str, upgrade: bool) -> Result<(), Error> {
+        let mut progress = self.pull_image(name, upgrade);
+
+        while let Some(p) = progress.next().await {
+            p?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_modules(self: &Self, name: &str, modules: &[String]) -> Result<(), Error> {
+        let content = format!("export POJDE_MODULES='{}'\n", modules.join(" "));
+
+        self.backend
+            .write_file(
+                &container_name(name),
+                POJDE_MODULES_FILE,
+                content.as_bytes(),
             )
-            .await;
+            .await
+            .map_err(|e| e.with_name(name))
+    }
+
+    fn validate_options(self: &Self, name: &str, options: &ApplyOptions) -> Result<(), Error> {
+        validate_name(name).map_err(|e| Error::Other {
+            name: name.to_owned(),
+            cause: e.into(),
+        })?;
 
-        match instances {
-            Ok(i) => Ok(i
+        if let Some(module) = options
+            .modules
+            .iter()
+            .find(|m| !POJDE_MODULES.iter().any(|(id, _)| *id == m.as_str()))
+        {
+            return Err(Error::Other {
+                name: name.to_owned(),
+                cause: format!("unknown module {:?}", module).into(),
+            });
+        }
+
+        validate_start_port(options.start_port).map_err(|e| Error::Other {
+            name: name.to_owned(),
+            cause: e.into(),
+        })
+    }
+
+    // Collects the ports of all containers except for the instance's own, if it exists already
+    pub async fn port_allocator(self: &Self, name: Option<&str>) -> Result<PortAllocator, Error> {
+        let own = name.map(|name| "/".to_owned() + &container_name(name));
+        let containers = self.backend.list("").await.map_err(|e| match name {
+            Some(name) => e.with_name(name),
+            None => e,
+        })?;
+
+        let mut reservations = vec![];
+        let mut published = HashSet::new();
+
+        for c in containers {
+            let ports = c
+                .ports
                 .iter()
-                .map(|c| {
-                    let mut ports = c
-                        .ports
-                        .iter()
-                        .map(|p| p.public_port.unwrap())
-                        .collect::<Vec<_>>();
-                    ports.sort();
-
-                    Instance {
-                        name: c.names[0]
-                            .strip_prefix(&("/".to_owned() + POJDE_PREFIX))
-                            .unwrap()
-                            .to_string(),
-                        start_port: ports.first().copied(),
-                        end_port: ports.last().copied(),
-                        status: c.state.to_owned(),
-                    }
+                .filter_map(|p| p.public_port)
+                .collect::<Vec<_>>();
+            published.extend(ports.iter().copied());
+
+            if c.names.iter().any(|n| Some(n) == own.as_ref()) {
+                continue;
+            }
+
+            let owner = c
+                .names
+                .first()
+                .map_or(c.id.as_str(), |n| n.trim_start_matches('/'))
+                .to_owned();
+            let config = c
+                .labels
+                .get(CONFIG_LABEL)
+                .and_then(|config| serde_json::from_str::<InstanceConfig>(config).ok());
+
+            match config {
+                // Stopped containers don't publish their ports, but keep them reserved
+                Some(config) => {
+                    let (start, end) = port_range(config.start_port);
+
+                    reservations.push(Reservation { owner, start, end });
+                }
+                None => reservations.extend(ports.iter().map(|port| Reservation {
+                    owner: owner.to_owned(),
+                    start: *port,
+                    end: *port,
+                })),
+            }
+        }
+
+        Ok(PortAllocator::new(
+            reservations,
+            published,
+            self.check_host_ports,
+        ))
+    }
+
+    async fn check_ports(self: &Self, name: &str, start_port: u64) -> Result<(), Error> {
+        self.port_allocator(Some(name))
+            .await?
+            .check(start_port)
+            .map_err(|e| Error::PortAllocated {
+                name: name.to_owned(),
+                cause: e.into(),
+            })
+    }
+
+    // Keeps the ports of existing instances and finds a free block for new ones
+    pub async fn allocate_ports(self: &Self, name: &str) -> Result<u64, Error> {
+        if let Some(i) = self
+            .get_instances()
+            .await?
+            .into_iter()
+            .find(|i| i.name == name)
+        {
+            if let Some(start_port) = i.config.map(|c| c.start_port).or(i.start_port) {
+                return Ok(start_port);
+            }
+        }
+
+        self.next_free(Some(name)).await
+    }
+
+    // Finds a free block of ports for an instance which doesn't have a name yet
+    pub async fn next_free_ports(self: &Self) -> Result<u64, Error> {
+        self.next_free(None).await
+    }
+
+    async fn next_free(self: &Self, name: Option<&str>) -> Result<u64, Error> {
+        self.port_allocator(name)
+            .await?
+            .next_free(DEFAULT_START_PORT)
+            .ok_or_else(|| Error::PortAllocated {
+                name: name.unwrap_or_default().to_owned(),
+                cause: "no free block of ports left".into(),
+            })
+    }
+
+    // Computes what `apply` would change without touching anything
+    pub async fn plan_apply(
+        self: &Self,
+        name: &str,
+        options: &ApplyOptions,
+    ) -> Result<Vec<PlannedChange>, Error> {
+        self.validate_options(name, options)?;
+
+        let mut changes = vec![];
+
+        let image = self.get_image();
+        if options.upgrade
+            || !self
+                .backend
+                .image_exists(&image)
+                .await
+                .map_err(|e| e.with_name(name))?
+        {
+            changes.push(PlannedChange::new(Operation::Pull, Resource::Image, &image));
+        }
+
+        let ports = Some(port_range(options.start_port));
+        let full_name = container_name(name);
+
+        match self
+            .get_instances()
+            .await?
+            .into_iter()
+            .find(|i| i.name == name)
+        {
+            None => {
+                self.check_ports(name, options.start_port).await?;
+
+                changes.push(
+                    PlannedChange::new(Operation::Create, Resource::Container, &full_name)
+                        .with_ports(None, ports),
+                )
+            }
+            Some(i) if options.recreate => {
+                self.check_ports(name, options.start_port).await?;
+
+                let before = i.start_port.zip(i.end_port);
+
+                changes.push(
+                    PlannedChange::new(Operation::Recreate, Resource::Container, &full_name)
+                        .with_ports(before, ports),
+                )
+            }
+            Some(i) => {
+                check_unchanged(&i, options)?;
+
+                if i.status != InstanceStatus::Running {
+                    changes.push(PlannedChange::new(
+                        Operation::Start,
+                        Resource::Container,
+                        &full_name,
+                    ))
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    // Returns the start port of the instance, which only differs from the requested one for instances created by older versions
+    pub async fn apply(self: &Self, name: &str, options: &ApplyOptions) -> Result<u64, Error> {
+        self.validate_options(name, options)?;
+
+        let existing = self
+            .get_instances()
+            .await?
+            .into_iter()
+            .find(|i| i.name == name);
+        let exists = existing.is_some();
+
+        let start_port = match &existing {
+            Some(i) if !options.recreate => {
+                check_unchanged(i, options)?;
+
+                i.start_port.unwrap_or(options.start_port)
+            }
+            _ => {
+                self.check_ports(name, options.start_port).await?;
+
+                options.start_port
+            }
+        };
+
+        self.ensure_image(name, options.upgrade).await?;
+
+        if exists && options.recreate {
+            self.backend
+                .remove(&container_name(name))
+                .await
+                .map_err(|e| e.with_name(name))?;
+        }
+
+        if !exists || options.recreate {
+            self.create(name, options).await?;
+            self.issue_certificate(name).await?;
+        }
+
+        if !options.modules.is_empty() {
+            self.write_modules(name, &options.modules).await?;
+        }
+
+        let details = self
+            .backend
+            .inspect(&container_name(name))
+            .await
+            .map_err(|e| e.with_name(name))?;
+        if !details.running {
+            self.start(name).await?;
+        }
+
+        Ok(start_port)
+    }
+
+    // Computes what `remove` would delete without touching anything
+    pub async fn plan_remove(
+        self: &Self,
+        names: &[String],
+        scope: &RemoveScope,
+    ) -> Result<Vec<PlannedChange>, Error> {
+        let existing = self.get_instances().await?;
+
+        let mut changes = names
+            .iter()
+            .filter_map(|name| existing.iter().find(|i| &i.name == name))
+            .map(|i| {
+                PlannedChange::new(
+                    Operation::Remove,
+                    Resource::Container,
+                    &container_name(&i.name),
+                )
+                .with_ports(i.start_port.zip(i.end_port), None)
+            })
+            .collect::<Vec<_>>();
+
+        let others = existing.iter().any(|i| !names.contains(&i.name));
+        changes.extend(
+            scope
+                .removable_volumes(names, others)
+                .iter()
+                .map(|volume| PlannedChange::new(Operation::Remove, Resource::Volume, volume)),
+        );
+
+        Ok(changes)
+    }
+
+    // Returns the volumes which have been deleted; missing ones and the CA while it is still in use are skipped
+    pub async fn remove(
+        self: &Self,
+        names: &[String],
+        scope: &RemoveScope,
+    ) -> Result<Vec<String>, Error> {
+        let others = self
+            .list_containers()
+            .await?
+            .iter()
+            .any(|(name, _)| !names.contains(name));
+
+        for name in names {
+            self.backend
+                .remove(&container_name(name))
+                .await
+                .map_err(|e| e.with_name(name))?;
+        }
+
+        let mut removed = vec![];
+        for volume in scope.removable_volumes(names, others) {
+            match self.backend.remove_volume(&volume).await {
+                Ok(_) => removed.push(volume),
+                Err(Error::NotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(removed)
+    }
+
+    pub async fn start(self: &Self, name: &str) -> Result<(), Error> {
+        self.backend
+            .start(&container_name(name))
+            .await
+            .map_err(|e| e.with_name(name))
+    }
+
+    pub async fn stop(self: &Self, name: &str) -> Result<(), Error> {
+        self.backend
+            .stop(&container_name(name))
+            .await
+            .map_err(|e| e.with_name(name))
+    }
+
+    pub async fn restart(self: &Self, name: &str) -> Result<(), Error> {
+        self.backend
+            .restart(&container_name(name))
+            .await
+            .map_err(|e| e.with_name(name))
+    }
+
+    pub async fn run(self: &Self, name: &str, action: LifecycleAction) -> Result<(), Error> {
+        match action {
+            LifecycleAction::Start => self.start(name).await,
+            LifecycleAction::Stop => self.stop(name).await,
+            LifecycleAction::Restart => self.restart(name).await,
+        }
+    }
+
+    // Runs the action on all instances, even if some of them fail
+    pub async fn run_all(
+        self: &Self,
+        names: &[String],
+        action: LifecycleAction,
+    ) -> Vec<(String, Result<(), Error>)> {
+        join_all(
+            names
+                .iter()
+                .map(|name| async move { (name.to_owned(), self.run(name, action).await) }),
+        )
+        .await
+    }
+
+    pub async fn get_logs(
+        self: &Self,
+        name: &str,
+        query: &LogQuery,
+    ) -> BoxStream<'_, Result<Chunk, Error>> {
+        let name = name.to_owned();
+
+        self.backend
+            .logs(&container_name(&name), query)
+            .map(move |c| c.map_err(|e| e.with_name(&name)))
+            .boxed()
+    }
+
+    // Pairs of instance names and their containers
+    async fn list_containers(self: &Self) -> Result<Vec<(String, ContainerSummary)>, Error> {
+        let prefix = "/".to_owned() + POJDE_PREFIX;
+
+        // Docker's name filter also matches substrings, i.e. `/my-pojde-test`
+        Ok(self
+            .backend
+            .list(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|c| {
+                let name = c
+                    .names
+                    .iter()
+                    .find_map(|n| n.strip_prefix(&prefix))
+                    .map(|n| n.to_owned());
+
+                name.map(|n| (n, c))
+            })
+            .collect())
+    }
+
+    // Literal names are kept even if their instance doesn't exist, so that operations can report them
+    pub async fn resolve(self: &Self, selector: &Selector) -> Result<Vec<String>, Error> {
+        let containers = self.list_containers().await?;
+
+        let mut candidates = vec![];
+        if selector.all || (selector.patterns.is_empty() && !selector.labels.is_empty()) {
+            candidates.extend(containers.iter().map(|(name, _)| name.to_owned()));
+        }
+
+        for pattern in &selector.patterns {
+            if !is_glob(pattern) {
+                candidates.push(pattern.to_owned());
+
+                continue;
+            }
+
+            let glob = Pattern::new(pattern).map_err(|e| Error::Other {
+                name: pattern.to_owned(),
+                cause: e.into(),
+            })?;
+
+            candidates.extend(
+                containers
+                    .iter()
+                    .filter(|(name, _)| glob.matches(name))
+                    .map(|(name, _)| name.to_owned()),
+            );
+        }
+
+        let mut names: Vec<String> = vec![];
+        for name in candidates {
+            if names.contains(&name) {
+                continue;
+            }
+
+            if !selector.labels.is_empty() {
+                let labels = match containers.iter().find(|(n, _)| *n == name) {
+                    Some((_, c)) => &c.labels,
+                    None => continue,
+                };
+
+                if !selector
+                    .labels
+                    .iter()
+                    .all(|l| labels.get(&l.key) == Some(&l.value))
+                {
+                    continue;
+                }
+            }
+
+            names.push(name);
+        }
+
+        Ok(names)
+    }
+
+    // Streams changes of instances and their volumes as they happen, until it is dropped
+    pub fn watch(self: &Self) -> BoxStream<'_, Result<InstanceEvent, Error>> {
+        self.backend
+            .events()
+            .filter_map(|event| {
+                future::ready(match event {
+                    Ok(e) => InstanceEvent::from_container_event(e).map(Ok),
+                    Err(e) => Some(Err(e)),
                 })
-                .collect::<Vec<_>>()),
-            Err(e) => Err(e),
+            })
+            .boxed()
+    }
+
+    pub async fn get_instances(self: &Self) -> Result<Vec<Instance>, Error> {
+        let containers = self.list_containers().await?;
+
+        let instances = try_join_all(containers.into_iter().map(|(name, c)| async move {
+            let details = match self.backend.inspect(&c.id).await {
+                Ok(details) => details,
+                // The container has been removed since it was listed
+                Err(Error::NotFound { .. }) => return Ok(None),
+                Err(e) => return Err(e.with_name(&name)),
+            };
+
+            // Ports can be exposed without being published
+            let mut ports = c
+                .ports
+                .iter()
+                .filter_map(|p| p.public_port)
+                .collect::<Vec<_>>();
+            ports.sort();
+
+            let published = c
+                .ports
+                .iter()
+                .filter_map(|p| {
+                    p.public_port
+                        .map(|public_port| (p.private_port, public_port))
+                })
+                .collect();
+
+            let uptime = if details.running {
+                (Utc::now() - details.started_at).to_std().ok()
+            } else {
+                None
+            };
+
+            let config = c
+                .labels
+                .get(CONFIG_LABEL)
+                .and_then(|config| serde_json::from_str::<InstanceConfig>(config).ok());
+
+            // Stopped containers don't publish their ports
+            let (start_port, end_port) = match (ports.first(), ports.last(), &config) {
+                (Some(start), Some(end), _) => (Some(*start), Some(*end)),
+                (_, _, Some(config)) => {
+                    let (start, end) = port_range(config.start_port);
+
+                    (Some(start), Some(end))
+                }
+                _ => (None, None),
+            };
+
+            Ok(Some(Instance {
+                volumes: RemoveScope::all().volumes(&[name.clone()]),
+                name,
+                start_port,
+                end_port,
+                status: InstanceStatus::from_state(&c.state, details.exit_code),
+                health: InstanceHealth::from_status(&c.status),
+                uptime,
+                config,
+                host: self.host.to_owned(),
+                published,
+                image: c.image,
+                created: c.created,
+            }))
+        }))
+        .await?;
+
+        Ok(instances.into_iter().flatten().collect())
+    }
+
+    pub async fn services(self: &Self, name: &str) -> Result<Vec<Service>, Error> {
+        match self
+            .get_instances()
+            .await?
+            .into_iter()
+            .find(|i| i.name == name)
+        {
+            Some(i) => Ok(i.services()),
+            None => Err(Error::NotFound {
+                name: name.to_owned(),
+                cause: "no such instance".into(),
+            }),
         }
     }
 
+    pub async fn exec(
+        self: &Self,
+        name: &str,
+        cmd: Vec<String>,
+        tty: bool,
+    ) -> Result<ExecSession, Error> {
+        let name = name.to_owned();
+
+        let session = self
+            .backend
+            .exec_interactive(&container_name(&name), cmd, tty)
+            .await
+            .map_err(|e| e.with_name(&name))?;
+
+        Ok(ExecSession {
+            output: session
+                .output
+                .map(move |c| c.map_err(|e| e.with_name(&name)))
+                .boxed(),
+            ..session
+        })
+    }
+
     pub async fn enter(
         self: &Self,
         name: &str,
-    ) -> impl Stream<Item = Result<tty::TtyChunk, Error>> + '_ {
-        self.get_container(name).exec(
-            &ExecContainerOptions::builder()
-                .cmd(vec!["uname", "-a"])
-                .attach_stdout(true)
-                .attach_stderr(true)
-                .build(),
+        cmd: Option<Vec<String>>,
+    ) -> Result<ExecSession, Error> {
+        let cmd = cmd.unwrap_or_else(|| {
+            vec![
+                "/bin/sh".to_owned(),
+                "-c".to_owned(),
+                LOGIN_SHELL.to_owned(),
+            ]
+        });
+
+        self.exec(name, cmd, true).await
+    }
+
+    pub async fn resize(
+        self: &Self,
+        session_id: &str,
+        width: u16,
+        height: u16,
+    ) -> Result<(), Error> {
+        self.backend.resize_exec(session_id, width, height).await
+    }
+
+    async fn open_ca_volume(self: &Self) -> Result<(), Error> {
+        // The helper might be left over from an interrupted run
+        self.close_ca_volume().await?;
+        self.ensure_image(CA_HELPER, false).await?;
+
+        self.backend
+            .create(&ContainerSpec {
+                name: CA_HELPER.to_owned(),
+                image: self.get_image(),
+                ports: vec![],
+                volumes: vec![format!("{}:{}", POJDE_CA_VOLUME.0, POJDE_CA_VOLUME.1)],
+                privileged: false,
+                labels: HashMap::new(),
+                memory: None,
+                cpus: None,
+            })
+            .await
+    }
+
+    async fn close_ca_volume(self: &Self) -> Result<(), Error> {
+        match self.backend.remove(CA_HELPER).await {
+            Ok(_) | Err(Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn read_ca(self: &Self) -> Result<Option<CertificateAuthority>, Error> {
+        let cert = match self
+            .backend
+            .read_file(CA_HELPER, &ca_path(CA_CERT_FILE))
+            .await
+        {
+            Ok(cert) => cert,
+            Err(Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let key = self
+            .backend
+            .read_file(CA_HELPER, &ca_path(CA_KEY_FILE))
+            .await?;
+
+        CertificateAuthority::from_pem(
+            &String::from_utf8_lossy(&cert),
+            &String::from_utf8_lossy(&key),
         )
+        .map(Some)
+    }
+
+    async fn write_ca(self: &Self, ca: &CertificateAuthority) -> Result<(), Error> {
+        self.backend
+            .write_file(CA_HELPER, &ca_path(CA_CERT_FILE), ca.cert_pem().as_bytes())
+            .await?;
+        self.backend
+            .write_file(CA_HELPER, &ca_path(CA_KEY_FILE), ca.key_pem().as_bytes())
+            .await
+    }
+
+    async fn read_or_create_ca(self: &Self) -> Result<CertificateAuthority, Error> {
+        if let Some(ca) = self.read_ca().await? {
+            return Ok(ca);
+        }
+
+        let ca = CertificateAuthority::generate()?;
+        self.write_ca(&ca).await?;
+
+        Ok(ca)
+    }
+
+    async fn write_certificate(
+        self: &Self,
+        ca: &CertificateAuthority,
+        name: &str,
+    ) -> Result<(), Error> {
+        let leaf = ca.issue(name, &self.host)?;
+
+        self.backend
+            .write_file(
+                CA_HELPER,
+                &ca_path(&format!("instances/{}/server.pem", name)),
+                leaf.cert_pem.as_bytes(),
+            )
+            .await?;
+        self.backend
+            .write_file(
+                CA_HELPER,
+                &ca_path(&format!("instances/{}/server.key", name)),
+                leaf.key_pem.as_bytes(),
+            )
+            .await
+    }
+
+    // Creates the CA if it doesn't exist yet
+    async fn issue_certificate(self: &Self, name: &str) -> Result<(), Error> {
+        let _lock = self.ca_lock.lock().await;
+
+        self.open_ca_volume().await?;
+        let res = async {
+            let ca = self.read_or_create_ca().await?;
+
+            self.write_certificate(&ca, name).await
+        }
+        .await;
+        self.close_ca_volume().await?;
+
+        res.map_err(|e| e.with_name(name))
+    }
+
+    // Creates the CA if it doesn't exist yet
+    pub async fn get_ca_cert(self: &Self) -> Result<String, Error> {
+        let _lock = self.ca_lock.lock().await;
+
+        self.open_ca_volume().await?;
+        let res = self.read_or_create_ca().await;
+        self.close_ca_volume().await?;
+
+        Ok(res?.cert_pem().to_owned())
+    }
+
+    // Replaces the CA and the certificates of all instances, then restarts running instances so that they use them
+    pub async fn reset_ca(self: &Self) -> Result<(), Error> {
+        let instances = self.get_instances().await?;
+        let ca = CertificateAuthority::generate()?;
+
+        {
+            let _lock = self.ca_lock.lock().await;
+
+            self.open_ca_volume().await?;
+            let res = async {
+                self.write_ca(&ca).await?;
+
+                for instance in &instances {
+                    self.write_certificate(&ca, &instance.name)
+                        .await
+                        .map_err(|e| e.with_name(&instance.name))?;
+                }
+
+                Ok::<(), Error>(())
+            }
+            .await;
+            self.close_ca_volume().await?;
+
+            res?;
+        }
+
+        try_join_all(
+            instances
+                .iter()
+                .filter(|i| i.status == InstanceStatus::Running)
+                .map(|i| self.restart(&i.name)),
+        )
+        .await?;
+
+        Ok(())
     }
 }
@@ -1,3 +1,16 @@
+pub mod backend;
+pub mod ca;
+pub mod error;
+pub mod forward;
 pub mod instances;
+pub mod manifest;
+pub mod node;
+pub mod output;
+pub mod plan;
+pub mod ports;
+pub mod screen;
+pub mod tasks;
 pub mod update;
 pub mod widgets;
+
+pub use error::Error;
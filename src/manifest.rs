@@ -0,0 +1,340 @@
+use std::{collections::HashSet, fmt, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    error::Error,
+    instances::{
+        port_range, validate_name, validate_start_port, ApplyOptions, Instance, Instances,
+        RemoveScope, Resources, POJDE_MODULES,
+    },
+    plan::PlannedChange,
+};
+
+// Manifests with other versions are rejected so that the format can change later on
+pub static MANIFEST_VERSION: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManifestFormat {
+    Yaml,
+    Toml,
+}
+
+impl ManifestFormat {
+    // Anything but `.toml` is read as YAML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::Toml,
+            _ => Self::Yaml,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub version: u64,
+    #[serde(default)]
+    pub instances: Vec<InstanceManifest>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstanceManifest {
+    pub name: String,
+    pub start_port: u64,
+    #[serde(default)]
+    pub modules: Vec<String>,
+    #[serde(default)]
+    pub isolate: bool,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub resources: ResourcesManifest,
+    // Bind mounts, i.e. `/home/me/src:/src`
+    #[serde(default)]
+    pub volumes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourcesManifest {
+    pub cpus: Option<f64>,
+    // Bytes with an optional unit, i.e. `4g`
+    pub memory: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Create,
+    // Options which are fixed at creation time differ
+    Recreate,
+    // Modules are written and the instance is started if it isn't running
+    Update,
+    Prune,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Create => write!(f, "create"),
+            Self::Recreate => write!(f, "recreate"),
+            Self::Update => write!(f, "update"),
+            Self::Prune => write!(f, "prune"),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let name = path.display().to_string();
+
+        let content = fs::read_to_string(path).map_err(|e| Error::Other {
+            name: name.to_owned(),
+            cause: e.into(),
+        })?;
+
+        Self::parse(&content, ManifestFormat::from_path(path)).map_err(|e| Error::Other {
+            name,
+            cause: e.into(),
+        })
+    }
+
+    pub fn parse(content: &str, format: ManifestFormat) -> Result<Self, String> {
+        let manifest: Self = match format {
+            ManifestFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string())?,
+            ManifestFormat::Toml => toml::from_str(content).map_err(|e| e.to_string())?,
+        };
+
+        manifest.validate()?;
+
+        Ok(manifest)
+    }
+
+    fn validate(self: &Self) -> Result<(), String> {
+        if self.version != MANIFEST_VERSION {
+            return Err(format!(
+                "unsupported manifest version {}, expected {}",
+                self.version, MANIFEST_VERSION
+            ));
+        }
+
+        let mut names = HashSet::new();
+        for (i, instance) in self.instances.iter().enumerate() {
+            let context = |e: String| format!("instance {:?}: {}", instance.name, e);
+
+            validate_name(&instance.name).map_err(context)?;
+            instance.options(false).map_err(context)?;
+
+            if !names.insert(instance.name.as_str()) {
+                return Err(context("listed more than once".to_owned()));
+            }
+
+            validate_start_port(instance.start_port).map_err(context)?;
+            let (start, end) = port_range(instance.start_port);
+
+            if let Some(other) = self.instances[..i].iter().find(|other| {
+                let (other_start, other_end) = port_range(other.start_port);
+
+                start <= other_end && other_start <= end
+            }) {
+                return Err(context(format!(
+                    "ports {}-{} overlap with instance {:?}",
+                    start, end, other.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl InstanceManifest {
+    pub fn options(self: &Self, upgrade: bool) -> Result<ApplyOptions, String> {
+        if let Some(module) = self
+            .modules
+            .iter()
+            .find(|m| !POJDE_MODULES.iter().any(|(id, _)| *id == m.as_str()))
+        {
+            return Err(format!("unknown module {:?}", module));
+        }
+
+        if let Some(volume) = self.volumes.iter().find(|v| {
+            let mut parts = v.split(':');
+
+            !matches!(
+                (parts.next(), parts.next()),
+                (Some(source), Some(target)) if !source.is_empty() && target.starts_with('/')
+            )
+        }) {
+            return Err(format!(
+                "invalid volume {:?}, expected format source:/target",
+                volume
+            ));
+        }
+
+        if let Some(cpus) = self.resources.cpus {
+            if cpus.is_nan() || cpus <= 0.0 {
+                return Err(format!("invalid number of CPUs {}", cpus));
+            }
+        }
+
+        let memory = match &self.resources.memory {
+            Some(memory) => Some(parse_memory(memory)?),
+            None => None,
+        };
+
+        Ok(ApplyOptions {
+            start_port: self.start_port,
+            upgrade,
+            recreate: false,
+            isolate: self.isolate,
+            privileged: self.privileged,
+            modules: self.modules.clone(),
+            resources: Resources {
+                cpus: self.resources.cpus,
+                memory,
+            },
+            volumes: self.volumes.clone(),
+        })
+    }
+}
+
+// Parses sizes like Docker does, i.e. `512m` or `4g`; units are binary
+pub fn parse_memory(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+
+    let shift = match unit.to_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" => 10,
+        "m" | "mb" => 20,
+        "g" | "gb" => 30,
+        "t" | "tb" => 40,
+        _ => return Err(format!("invalid memory limit {:?}", s)),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1u64 << shift))
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("invalid memory limit {:?}", s))
+}
+
+// Compares the manifest with the existing instances; unlisted instances are only pruned if requested
+pub fn plan(existing: &[Instance], manifest: &Manifest, prune: bool) -> Vec<(String, Change)> {
+    let mut changes = manifest
+        .instances
+        .iter()
+        .map(|m| {
+            let change = match existing.iter().find(|i| i.name == m.name) {
+                None => Change::Create,
+                Some(i) => {
+                    let wanted = m.options(false).map(|o| o.config()).ok();
+
+                    // Instances created by older versions don't record their options
+                    if i.config.is_some() && i.config == wanted {
+                        Change::Update
+                    } else {
+                        Change::Recreate
+                    }
+                }
+            };
+
+            (m.name.to_owned(), change)
+        })
+        .collect::<Vec<_>>();
+
+    if prune {
+        changes.extend(
+            existing
+                .iter()
+                .filter(|i| !manifest.instances.iter().any(|m| m.name == i.name))
+                .map(|i| (i.name.to_owned(), Change::Prune)),
+        );
+    }
+
+    changes
+}
+
+// Applies the changes one after another so that failures don't stop the others
+pub async fn sync(
+    instances: &Instances,
+    manifest: &Manifest,
+    changes: &[(String, Change)],
+    upgrade: bool,
+) -> Vec<(String, Result<(), Error>)> {
+    let mut results = vec![];
+
+    for (name, change) in changes {
+        let res = match (change, manifest.instances.iter().find(|m| &m.name == name)) {
+            // Volumes are kept, as they can't be restored from the manifest
+            (Change::Prune, _) => instances
+                .remove(&[name.to_owned()], &RemoveScope::default())
+                .await
+                .map(|_| ()),
+            (change, Some(m)) => match m.options(upgrade) {
+                Ok(mut options) => {
+                    options.recreate = *change == Change::Recreate;
+
+                    instances.apply(name, &options).await.map(|_| ())
+                }
+                Err(e) => Err(Error::Other {
+                    name: name.to_owned(),
+                    cause: e.into(),
+                }),
+            },
+            (_, None) => Err(Error::NotFound {
+                name: name.to_owned(),
+                cause: "not listed in the manifest".into(),
+            }),
+        };
+
+        results.push((name.to_owned(), res));
+    }
+
+    results
+}
+
+// Expands the changes into what a sync would do, without touching anything
+pub async fn plan_sync(
+    instances: &Instances,
+    manifest: &Manifest,
+    changes: &[(String, Change)],
+    upgrade: bool,
+) -> Result<Vec<PlannedChange>, Error> {
+    let mut planned = vec![];
+
+    for (name, change) in changes {
+        let steps = match (change, manifest.instances.iter().find(|m| &m.name == name)) {
+            (Change::Prune, _) => {
+                instances
+                    .plan_remove(&[name.to_owned()], &RemoveScope::default())
+                    .await?
+            }
+            (change, Some(m)) => {
+                let mut options = m.options(upgrade).map_err(|e| Error::Other {
+                    name: name.to_owned(),
+                    cause: e.into(),
+                })?;
+                options.recreate = *change == Change::Recreate;
+
+                instances.plan_apply(name, &options).await?
+            }
+            (_, None) => vec![],
+        };
+
+        // The image is only pulled once
+        for step in steps {
+            if !planned.contains(&step) {
+                planned.push(step);
+            }
+        }
+    }
+
+    Ok(planned)
+}
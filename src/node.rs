@@ -0,0 +1,202 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process::{self, Stdio},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncReadExt,
+    process::{Child, Command},
+    time::sleep,
+};
+
+use crate::{
+    backend::{connect, Endpoint},
+    error::Error,
+};
+
+static REMOTE_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+// Distinguishes the socket directories of tunnels within one process
+static NEXT_TUNNEL: AtomicU64 = AtomicU64::new(0);
+static TUNNEL_ATTEMPTS: u32 = 50;
+static TUNNEL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl FromStr for Node {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, rest) = match s.split_once('@') {
+            Some((user, rest)) => (Some(user.to_owned()), rest),
+            None => (None, s),
+        };
+
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host, Some(port)),
+                Err(_) => return Err(format!("invalid port {:?}", port)),
+            },
+            None => (rest, None),
+        };
+
+        if host.is_empty() {
+            return Err("missing host".to_owned());
+        }
+
+        Ok(Self {
+            user,
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+
+        write!(f, "{}", self.host)?;
+
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Forwards a local socket to the remote Docker socket; the SSH process is killed and the socket is removed on drop
+pub struct Tunnel {
+    pub endpoint: Endpoint,
+    dir: PathBuf,
+    ssh: Child,
+}
+
+impl Tunnel {
+    pub async fn open(node: &Node) -> Result<Self, Error> {
+        let unreachable = |e: io::Error| Error::DaemonUnreachable {
+            name: node.to_string(),
+            cause: e.into(),
+        };
+
+        let dir = private_dir().map_err(unreachable)?;
+        let (endpoint, local) = match local_socket(&dir) {
+            Ok(socket) => socket,
+            Err(e) => {
+                fs::remove_dir_all(&dir).ok();
+
+                return Err(unreachable(e));
+            }
+        };
+
+        let mut ssh = Command::new("ssh");
+        ssh.args(&["-nNT", "-o", "ExitOnForwardFailure=yes", "-L"])
+            .arg(format!("{}:{}", local, REMOTE_DOCKER_SOCKET))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(port) = node.port {
+            ssh.arg("-p").arg(port.to_string());
+        }
+
+        match &node.user {
+            Some(user) => ssh.arg(format!("{}@{}", user, node.host)),
+            None => ssh.arg(&node.host),
+        };
+
+        let child = match ssh.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                fs::remove_dir_all(&dir).ok();
+
+                return Err(unreachable(e));
+            }
+        };
+
+        // From here on, dropping the tunnel cleans up
+        let mut tunnel = Self {
+            endpoint,
+            dir,
+            ssh: child,
+        };
+
+        for _ in 0..TUNNEL_ATTEMPTS {
+            if connect(&tunnel.endpoint).await.is_ok() {
+                return Ok(tunnel);
+            }
+
+            if let Ok(Some(status)) = tunnel.ssh.try_wait() {
+                let mut stderr = String::new();
+                if let Some(mut output) = tunnel.ssh.stderr.take() {
+                    output.read_to_string(&mut stderr).await.ok();
+                }
+
+                return Err(Error::DaemonUnreachable {
+                    name: node.to_string(),
+                    cause: format!("ssh exited with {}: {}", status, stderr.trim()).into(),
+                });
+            }
+
+            sleep(TUNNEL_INTERVAL).await;
+        }
+
+        Err(Error::DaemonUnreachable {
+            name: node.to_string(),
+            cause: "timed out while waiting for the SSH tunnel".into(),
+        })
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+// Only the current user may access the directory; creating it fails if it already exists
+fn private_dir() -> io::Result<PathBuf> {
+    let dir = env::temp_dir().join(format!(
+        "pojdectl-{}-{}",
+        process::id(),
+        NEXT_TUNNEL.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir)?;
+
+    Ok(dir)
+}
+
+// The endpoint to connect to and the local side of `ssh -L`
+#[cfg(unix)]
+fn local_socket(dir: &Path) -> io::Result<(Endpoint, String)> {
+    let socket = dir.join("docker.sock");
+    let local = socket.to_string_lossy().into_owned();
+
+    Ok((Endpoint::Unix(socket), local))
+}
+
+// Docker clients can't use Unix sockets on other platforms, so these fall back to a local TCP port
+#[cfg(not(unix))]
+fn local_socket(_: &Path) -> io::Result<(Endpoint, String)> {
+    let addr = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .to_string();
+
+    Ok((Endpoint::Tcp(addr.clone()), addr))
+}
@@ -0,0 +1,182 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::{
+    error::Cause,
+    instances::{Instance, InstanceEvent, InstanceEventKind, InstanceStatus, Service},
+    Error,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    // Table with additional columns
+    Wide,
+    Json,
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Self::Table),
+            "wide" => Ok(Self::Wide),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            _ => Err("no match"),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn is_machine_readable(self: &Self) -> bool {
+        matches!(self, Self::Json | Self::Yaml)
+    }
+}
+
+// Field names and value formats are part of the CLI's interface; only add to them
+#[derive(Serialize)]
+pub struct InstanceOutput {
+    pub name: String,
+    pub status: String,
+    pub exit_code: Option<u64>,
+    pub health: Option<String>,
+    pub ports: Option<PortRange>,
+    pub image: String,
+    pub created: String,
+    pub uptime_seconds: Option<u64>,
+    pub volumes: Vec<String>,
+    pub services: Vec<Service>,
+}
+
+#[derive(Serialize)]
+pub struct PortRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl From<&Instance> for InstanceOutput {
+    fn from(i: &Instance) -> Self {
+        let (status, exit_code) = match i.status {
+            InstanceStatus::Created => ("created", None),
+            InstanceStatus::Running => ("running", None),
+            InstanceStatus::Paused => ("paused", None),
+            InstanceStatus::Restarting => ("restarting", None),
+            InstanceStatus::Exited(code) => ("exited", Some(code)),
+            InstanceStatus::Dead => ("dead", None),
+            InstanceStatus::Removing => ("removing", None),
+        };
+
+        let ports = match (i.start_port, i.end_port) {
+            (Some(start), Some(end)) => Some(PortRange { start, end }),
+            _ => None,
+        };
+
+        Self {
+            name: i.name.to_owned(),
+            status: status.to_owned(),
+            exit_code,
+            health: i.health.map(|h| h.to_string()),
+            ports,
+            image: i.image.to_owned(),
+            created: i.created.to_rfc3339(),
+            uptime_seconds: i.uptime.map(|u| u.as_secs()),
+            volumes: i.volumes.to_owned(),
+            services: i.services(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct EventOutput {
+    pub time: String,
+    pub name: String,
+    pub event: String,
+    pub exit_code: Option<u64>,
+    pub health: Option<String>,
+    pub volume: Option<String>,
+}
+
+impl From<&InstanceEvent> for EventOutput {
+    fn from(e: &InstanceEvent) -> Self {
+        let (event, exit_code, health, volume) = match &e.kind {
+            InstanceEventKind::Created => ("created", None, None, None),
+            InstanceEventKind::Started => ("started", None, None, None),
+            InstanceEventKind::Died(code) => ("died", *code, None, None),
+            InstanceEventKind::Removed => ("removed", None, None, None),
+            InstanceEventKind::HealthChanged(health) => {
+                ("health_changed", None, Some(health.to_string()), None)
+            }
+            InstanceEventKind::VolumeCreated(volume) => {
+                ("volume_created", None, None, Some(volume.to_owned()))
+            }
+            InstanceEventKind::VolumeRemoved(volume) => {
+                ("volume_removed", None, None, Some(volume.to_owned()))
+            }
+        };
+
+        Self {
+            time: e.time.to_rfc3339(),
+            name: e.name.to_owned(),
+            event: event.to_owned(),
+            exit_code,
+            health,
+            volume,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OperationResult {
+    pub name: String,
+    pub operation: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl OperationResult {
+    pub fn new(name: &str, operation: &str, res: &Result<(), Error>) -> Self {
+        Self {
+            name: name.to_owned(),
+            operation: operation.to_owned(),
+            success: res.is_ok(),
+            error: res.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+}
+
+pub fn serialize<T: Serialize + ?Sized>(format: OutputFormat, value: &T) -> Result<String, Error> {
+    let res: Result<String, Cause> = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(value)
+            .map(|s| s + "\n")
+            .map_err(|e| e.into()),
+        OutputFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.into()),
+        OutputFormat::Table | OutputFormat::Wide => Err("tables can't be serialized".into()),
+    };
+
+    res.map_err(|cause| Error::Other {
+        name: "output".to_owned(),
+        cause,
+    })
+}
+
+// Serializes one item of a stream, so that JSON is emitted as one object per line and YAML as one document per item
+pub fn serialize_item<T: Serialize + ?Sized>(
+    format: OutputFormat,
+    value: &T,
+) -> Result<String, Error> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_string(value)
+                .map(|s| s + "\n")
+                .map_err(|e| Error::Other {
+                    name: "output".to_owned(),
+                    cause: e.into(),
+                })
+        }
+        _ => serialize(format, value),
+    }
+}
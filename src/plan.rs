@@ -0,0 +1,135 @@
+use std::fmt;
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Pull,
+    Create,
+    // Removes and creates the container, volumes are kept
+    Recreate,
+    Start,
+    Remove,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    Image,
+    Container,
+    Volume,
+}
+
+// A change which `apply`, `remove` or a manifest sync would make
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlannedChange {
+    pub operation: Operation,
+    pub resource: Resource,
+    pub name: String,
+    // Port ranges of containers, i.e. `8000-8006`
+    pub ports_before: Option<String>,
+    pub ports_after: Option<String>,
+}
+
+impl PlannedChange {
+    pub fn new(operation: Operation, resource: Resource, name: &str) -> Self {
+        Self {
+            operation,
+            resource,
+            name: name.to_owned(),
+            ports_before: None,
+            ports_after: None,
+        }
+    }
+
+    pub fn with_ports(self: Self, before: Option<(u64, u64)>, after: Option<(u64, u64)>) -> Self {
+        let format =
+            |ports: Option<(u64, u64)>| ports.map(|(start, end)| format!("{}-{}", start, end));
+
+        Self {
+            ports_before: format(before),
+            ports_after: format(after),
+            ..self
+        }
+    }
+
+    fn symbol(self: &Self) -> char {
+        match self.operation {
+            Operation::Pull | Operation::Create => '+',
+            Operation::Recreate | Operation::Start => '~',
+            Operation::Remove => '-',
+        }
+    }
+
+    fn color(self: &Self) -> &'static str {
+        match self.operation {
+            Operation::Pull | Operation::Create => "\x1b[32m",
+            Operation::Recreate | Operation::Start => "\x1b[33m",
+            Operation::Remove => "\x1b[31m",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pull => write!(f, "pull"),
+            Self::Create => write!(f, "create"),
+            Self::Recreate => write!(f, "recreate"),
+            Self::Start => write!(f, "start"),
+            Self::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image => write!(f, "image"),
+            Self::Container => write!(f, "container"),
+            Self::Volume => write!(f, "volume"),
+        }
+    }
+}
+
+impl fmt::Display for PlannedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} ({}",
+            self.symbol(),
+            self.resource,
+            self.name,
+            self.operation
+        )?;
+
+        match (&self.ports_before, &self.ports_after) {
+            (Some(before), Some(after)) if before != after => {
+                write!(f, ", ports {} -> {}", before, after)?
+            }
+            (_, Some(ports)) | (Some(ports), None) => write!(f, ", ports {}", ports)?,
+            (None, None) => {}
+        }
+
+        write!(f, ")")
+    }
+}
+
+// Renders the changes like a diff, one line per change
+pub fn diff(changes: &[PlannedChange], color: bool) -> String {
+    if changes.is_empty() {
+        return "No changes.\n".to_owned();
+    }
+
+    changes
+        .iter()
+        .map(|c| {
+            if color {
+                format!("{}{}\x1b[0m\n", c.color(), c)
+            } else {
+                format!("{}\n", c)
+            }
+        })
+        .collect()
+}
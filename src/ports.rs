@@ -0,0 +1,102 @@
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, TcpListener},
+};
+
+use crate::instances::port_range;
+
+// Instances are placed above the ports reserved for system services
+pub static DEFAULT_START_PORT: u64 = 8000;
+static MAX_PORT: u64 = u16::MAX as u64;
+
+// A block of ports which is in use by a container
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reservation {
+    pub owner: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+// Finds blocks of ports for instances which don't collide with containers or host listeners
+pub struct PortAllocator {
+    reservations: Vec<Reservation>,
+    // Docker binds published ports on the host itself, so these aren't probed
+    published: HashSet<u64>,
+    check_host: bool,
+}
+
+impl PortAllocator {
+    pub fn new(reservations: Vec<Reservation>, published: HashSet<u64>, check_host: bool) -> Self {
+        Self {
+            reservations,
+            published,
+            check_host,
+        }
+    }
+
+    pub fn check(self: &Self, start_port: u64) -> Result<(), String> {
+        match self.conflict(start_port) {
+            Some((message, _)) => Err(message),
+            None => Ok(()),
+        }
+    }
+
+    // Returns the first free block at or after `from`
+    pub fn next_free(self: &Self, from: u64) -> Option<u64> {
+        let mut start_port = from;
+
+        while port_range(start_port).1 <= MAX_PORT {
+            match self.conflict(start_port) {
+                Some((_, next)) => start_port = next,
+                None => return Some(start_port),
+            }
+        }
+
+        None
+    }
+
+    // Describes the first collision and the next start port which could be free
+    fn conflict(self: &Self, start_port: u64) -> Option<(String, u64)> {
+        let (start, end) = port_range(start_port);
+
+        if start == 0 || end > MAX_PORT {
+            return Some((
+                format!("ports {}-{} are out of range", start, end),
+                MAX_PORT + 1,
+            ));
+        }
+
+        if let Some(r) = self
+            .reservations
+            .iter()
+            .filter(|r| start <= r.end && r.start <= end)
+            .max_by_key(|r| r.end)
+        {
+            return Some((
+                format!(
+                    "ports {}-{} overlap with ports {}-{} of {}",
+                    start, end, r.start, r.end, r.owner
+                ),
+                r.end + 1,
+            ));
+        }
+
+        if self.check_host {
+            if let Some(port) = (start..=end)
+                .rev()
+                .find(|port| !self.published.contains(port) && !is_free(*port))
+            {
+                return Some((
+                    format!("port {} is already in use on the host", port),
+                    port + 1,
+                ));
+            }
+        }
+
+        None
+    }
+}
+
+fn is_free(port: u64) -> bool {
+    TcpListener::bind((Ipv4Addr::UNSPECIFIED, port as u16)).is_ok()
+}
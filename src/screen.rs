@@ -0,0 +1,514 @@
+use std::mem;
+
+use vte::{Params, Parser, Perform};
+
+// Tab stops are fixed, as is common for VT100 emulators
+static TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Default,
+    // The 16 ANSI colors, the 6x6x6 cube and the grayscale ramp
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub c: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            c: ' ',
+            fg: Color::Default,
+            bg: Color::Default,
+            bold: false,
+            underline: false,
+            inverse: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Cursor {
+    row: usize,
+    col: usize,
+}
+
+// The state of a VT100/xterm-compatible terminal which is fed with the output of a TTY session
+pub struct Screen {
+    parser: Parser,
+    grid: Grid,
+}
+
+struct Grid {
+    cols: usize,
+    rows: usize,
+    lines: Vec<Vec<Cell>>,
+    // The primary screen while full-screen programs use the alternate one
+    primary: Option<Vec<Vec<Cell>>>,
+    cursor: Cursor,
+    saved_cursor: Cursor,
+    // Attributes of newly printed characters
+    pen: Cell,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    // The cursor stays on the last column until the next character is printed
+    wrap_pending: bool,
+    autowrap: bool,
+    cursor_visible: bool,
+    title: String,
+    // Answers to queries, i.e. for the cursor position, which must be written to the session
+    responses: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            parser: Parser::new(),
+            grid: Grid::new(cols.max(1), rows.max(1)),
+        }
+    }
+
+    pub fn process(self: &mut Self, bytes: &[u8]) {
+        for b in bytes {
+            self.parser.advance(&mut self.grid, *b);
+        }
+    }
+
+    pub fn resize(self: &mut Self, cols: usize, rows: usize) {
+        self.grid.resize(cols.max(1), rows.max(1));
+    }
+
+    pub fn size(self: &Self) -> (usize, usize) {
+        (self.grid.cols, self.grid.rows)
+    }
+
+    pub fn line(self: &Self, row: usize) -> &[Cell] {
+        &self.grid.lines[row]
+    }
+
+    // Returns `None` if programs have hidden the cursor
+    pub fn cursor(self: &Self) -> Option<(usize, usize)> {
+        if self.grid.cursor_visible {
+            Some((self.grid.cursor.row, self.grid.cursor.col))
+        } else {
+            None
+        }
+    }
+
+    pub fn title(self: &Self) -> &str {
+        &self.grid.title
+    }
+
+    pub fn take_responses(self: &mut Self) -> Vec<u8> {
+        mem::take(&mut self.grid.responses)
+    }
+
+    // Text between two (row, column) positions, inclusive, with trailing blanks of lines removed
+    pub fn text(self: &Self, start: (usize, usize), end: (usize, usize)) -> String {
+        let (start, end) = if start <= end {
+            (start, end)
+        } else {
+            (end, start)
+        };
+
+        (start.0..=end.0.min(self.grid.rows - 1))
+            .map(|row| {
+                let line = &self.grid.lines[row];
+                let from = if row == start.0 { start.1 } else { 0 };
+                let to = if row == end.0 {
+                    (end.1 + 1).min(line.len())
+                } else {
+                    line.len()
+                };
+
+                line[from.min(to)..to]
+                    .iter()
+                    .map(|c| c.c)
+                    .collect::<String>()
+                    .trim_end()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn contents(self: &Self) -> String {
+        self.text((0, 0), (self.grid.rows - 1, self.grid.cols - 1))
+    }
+}
+
+// Resizes the lines of a screen and drops lines from the top so that the cursor's line stays
+// visible; returns the number of dropped lines
+fn fit_lines(lines: &mut Vec<Vec<Cell>>, cursor_row: usize, cols: usize, rows: usize) -> usize {
+    for line in lines.iter_mut() {
+        line.resize(cols, Cell::default());
+    }
+
+    let dropped = (cursor_row + 1).saturating_sub(rows);
+    lines.drain(..dropped.min(lines.len()));
+    lines.resize(rows, vec![Cell::default(); cols]);
+
+    dropped
+}
+
+impl Grid {
+    fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows,
+            lines: vec![vec![Cell::default(); cols]; rows],
+            primary: None,
+            cursor: Cursor::default(),
+            saved_cursor: Cursor::default(),
+            pen: Cell::default(),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            wrap_pending: false,
+            autowrap: true,
+            cursor_visible: true,
+            title: String::new(),
+            responses: vec![],
+        }
+    }
+
+    // Erased cells keep the current background, like xterm does
+    fn blank(self: &Self) -> Cell {
+        Cell {
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
+    }
+
+    fn blank_line(self: &Self) -> Vec<Cell> {
+        vec![self.blank(); self.cols]
+    }
+
+    fn resize(self: &mut Self, cols: usize, rows: usize) {
+        let dropped = fit_lines(&mut self.lines, self.cursor.row, cols, rows);
+        self.cursor.row -= dropped;
+
+        // The saved cursor belongs to the primary screen while the alternate one is shown
+        let saved_dropped = match self.primary.as_mut() {
+            Some(primary) => fit_lines(primary, self.saved_cursor.row, cols, rows),
+            None => dropped,
+        };
+        self.saved_cursor.row = self.saved_cursor.row.saturating_sub(saved_dropped);
+
+        self.cols = cols;
+        self.rows = rows;
+
+        for cursor in [&mut self.cursor, &mut self.saved_cursor].iter_mut() {
+            cursor.row = cursor.row.min(rows - 1);
+            cursor.col = cursor.col.min(cols - 1);
+        }
+
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.wrap_pending = false;
+    }
+
+    fn restore_cursor(self: &mut Self) {
+        let Cursor { row, col } = self.saved_cursor;
+
+        self.move_to(row, col);
+    }
+
+    fn scroll_up(self: &mut Self, n: usize) {
+        for _ in 0..n.min(self.scroll_bottom - self.scroll_top + 1) {
+            self.lines.remove(self.scroll_top);
+            self.lines.insert(self.scroll_bottom, self.blank_line());
+        }
+    }
+
+    fn scroll_down(self: &mut Self, n: usize) {
+        for _ in 0..n.min(self.scroll_bottom - self.scroll_top + 1) {
+            self.lines.remove(self.scroll_bottom);
+            self.lines.insert(self.scroll_top, self.blank_line());
+        }
+    }
+
+    fn linefeed(self: &mut Self) {
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row < self.rows - 1 {
+            self.cursor.row += 1;
+        }
+    }
+
+    fn reverse_index(self: &mut Self) {
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.cursor.row = self.cursor.row.saturating_sub(1);
+        }
+    }
+
+    fn erase(self: &mut Self, row: usize, from: usize, to: usize) {
+        let blank = self.blank();
+
+        self.lines[row][from.min(self.cols)..to.min(self.cols)]
+            .iter_mut()
+            .for_each(|c| *c = blank);
+    }
+
+    fn move_to(self: &mut Self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.col = col.min(self.cols - 1);
+    }
+
+    fn set_alternate_screen(self: &mut Self, enabled: bool, save_cursor: bool) {
+        if enabled && self.primary.is_none() {
+            if save_cursor {
+                self.saved_cursor = self.cursor;
+            }
+
+            let alternate = vec![vec![Cell::default(); self.cols]; self.rows];
+            self.primary = Some(mem::replace(&mut self.lines, alternate));
+        } else if !enabled {
+            if let Some(primary) = self.primary.take() {
+                self.lines = primary;
+
+                if save_cursor {
+                    self.restore_cursor();
+                }
+            }
+        }
+    }
+
+    fn set_private_mode(self: &mut Self, mode: u16, enabled: bool) {
+        match mode {
+            7 => self.autowrap = enabled,
+            25 => self.cursor_visible = enabled,
+            47 | 1047 => self.set_alternate_screen(enabled, false),
+            1049 => self.set_alternate_screen(enabled, true),
+            _ => {}
+        }
+    }
+
+    fn set_graphic_rendition(self: &mut Self, args: &[u16]) {
+        if args.is_empty() {
+            self.pen = Cell::default();
+        }
+
+        let mut args = args.iter().copied();
+
+        while let Some(arg) = args.next() {
+            match arg {
+                0 => self.pen = Cell::default(),
+                1 => self.pen.bold = true,
+                4 => self.pen.underline = true,
+                7 => self.pen.inverse = true,
+                22 => self.pen.bold = false,
+                24 => self.pen.underline = false,
+                27 => self.pen.inverse = false,
+                30..=37 => self.pen.fg = Color::Indexed((arg - 30) as u8),
+                38 => self.pen.fg = extended_color(&mut args),
+                39 => self.pen.fg = Color::Default,
+                40..=47 => self.pen.bg = Color::Indexed((arg - 40) as u8),
+                48 => self.pen.bg = extended_color(&mut args),
+                49 => self.pen.bg = Color::Default,
+                90..=97 => self.pen.fg = Color::Indexed((arg - 90 + 8) as u8),
+                100..=107 => self.pen.bg = Color::Indexed((arg - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Parses the arguments of `38;5;n` and `38;2;r;g;b`
+fn extended_color(args: &mut impl Iterator<Item = u16>) -> Color {
+    match args.next() {
+        Some(5) => Color::Indexed(args.next().unwrap_or(0) as u8),
+        Some(2) => Color::Rgb(
+            args.next().unwrap_or(0) as u8,
+            args.next().unwrap_or(0) as u8,
+            args.next().unwrap_or(0) as u8,
+        ),
+        _ => Color::Default,
+    }
+}
+
+impl Perform for Grid {
+    fn print(&mut self, c: char) {
+        if self.wrap_pending && self.autowrap {
+            self.cursor.col = 0;
+            self.linefeed();
+        }
+        self.wrap_pending = false;
+
+        self.lines[self.cursor.row][self.cursor.col] = Cell { c, ..self.pen };
+
+        if self.cursor.col + 1 < self.cols {
+            self.cursor.col += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        self.wrap_pending = false;
+
+        match byte {
+            // Backspace
+            0x08 => self.cursor.col = self.cursor.col.saturating_sub(1),
+            // Horizontal tab
+            0x09 => {
+                self.cursor.col = ((self.cursor.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1)
+            }
+            // Line feed, vertical tab and form feed
+            0x0a | 0x0b | 0x0c => self.linefeed(),
+            // Carriage return
+            0x0d => self.cursor.col = 0,
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        let private = intermediates.first() == Some(&b'?');
+        let args = params
+            .iter()
+            .flat_map(|p| p.iter().copied())
+            .collect::<Vec<_>>();
+        // Missing and zero arguments both select the default
+        let arg = |i: usize, default: usize| {
+            args.get(i)
+                .copied()
+                .filter(|a| *a != 0)
+                .map(|a| a as usize)
+                .unwrap_or(default)
+        };
+        let Cursor { row, col } = self.cursor;
+
+        if action != 'm' {
+            self.wrap_pending = false;
+        }
+
+        match action {
+            'A' => self.move_to(row.saturating_sub(arg(0, 1)), col),
+            'B' | 'e' => self.move_to(row + arg(0, 1), col),
+            'C' | 'a' => self.move_to(row, col + arg(0, 1)),
+            'D' => self.move_to(row, col.saturating_sub(arg(0, 1))),
+            'E' => self.move_to(row + arg(0, 1), 0),
+            'F' => self.move_to(row.saturating_sub(arg(0, 1)), 0),
+            'G' | '`' => self.move_to(row, arg(0, 1) - 1),
+            'd' => self.move_to(arg(0, 1) - 1, col),
+            'H' | 'f' => self.move_to(arg(0, 1) - 1, arg(1, 1) - 1),
+            'J' => match args.first().copied().unwrap_or(0) {
+                0 => {
+                    self.erase(row, col, self.cols);
+                    (row + 1..self.rows).for_each(|r| self.erase(r, 0, self.cols));
+                }
+                1 => {
+                    (0..row).for_each(|r| self.erase(r, 0, self.cols));
+                    self.erase(row, 0, col + 1);
+                }
+                _ => (0..self.rows).for_each(|r| self.erase(r, 0, self.cols)),
+            },
+            'K' => match args.first().copied().unwrap_or(0) {
+                0 => self.erase(row, col, self.cols),
+                1 => self.erase(row, 0, col + 1),
+                _ => self.erase(row, 0, self.cols),
+            },
+            'L' if row >= self.scroll_top && row <= self.scroll_bottom => {
+                for _ in 0..arg(0, 1).min(self.scroll_bottom - row + 1) {
+                    self.lines.remove(self.scroll_bottom);
+                    self.lines.insert(row, self.blank_line());
+                }
+            }
+            'M' if row >= self.scroll_top && row <= self.scroll_bottom => {
+                for _ in 0..arg(0, 1).min(self.scroll_bottom - row + 1) {
+                    self.lines.remove(row);
+                    self.lines.insert(self.scroll_bottom, self.blank_line());
+                }
+            }
+            'P' => {
+                let blank = self.blank();
+                let line = &mut self.lines[row];
+
+                for _ in 0..arg(0, 1).min(self.cols - col) {
+                    line.remove(col);
+                    line.push(blank);
+                }
+            }
+            '@' => {
+                let blank = self.blank();
+                let line = &mut self.lines[row];
+
+                for _ in 0..arg(0, 1).min(self.cols - col) {
+                    line.insert(col, blank);
+                }
+                line.truncate(self.cols);
+            }
+            'X' => self.erase(row, col, col + arg(0, 1)),
+            'S' => self.scroll_up(arg(0, 1)),
+            'T' => self.scroll_down(arg(0, 1)),
+            'm' => self.set_graphic_rendition(&args),
+            'r' => {
+                let top = arg(0, 1) - 1;
+                let bottom = arg(1, self.rows).min(self.rows) - 1;
+
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                } else {
+                    self.scroll_top = 0;
+                    self.scroll_bottom = self.rows - 1;
+                }
+
+                self.move_to(0, 0);
+            }
+            'h' | 'l' if private => args
+                .iter()
+                .for_each(|mode| self.set_private_mode(*mode, action == 'h')),
+            's' => self.saved_cursor = self.cursor,
+            'u' => self.restore_cursor(),
+            'n' => match args.first() {
+                Some(5) => self.responses.extend_from_slice(b"\x1b[0n"),
+                Some(6) => self
+                    .responses
+                    .extend(format!("\x1b[{};{}R", row + 1, col + 1).into_bytes()),
+                _ => {}
+            },
+            // Identifies as a VT100 with advanced video option
+            'c' if intermediates.is_empty() => self.responses.extend_from_slice(b"\x1b[?1;2c"),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if !intermediates.is_empty() {
+            return;
+        }
+
+        self.wrap_pending = false;
+
+        match byte {
+            b'7' => self.saved_cursor = self.cursor,
+            b'8' => self.restore_cursor(),
+            b'D' => self.linefeed(),
+            b'E' => {
+                self.cursor.col = 0;
+                self.linefeed();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => *self = Grid::new(self.cols, self.rows),
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        // Window titles, i.e. as set by shells' prompts
+        if let [b"0", title] | [b"2", title] = params {
+            self.title = String::from_utf8_lossy(title).into_owned();
+        }
+    }
+}
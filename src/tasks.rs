@@ -0,0 +1,169 @@
+use std::{
+    future::Future,
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+};
+
+use tokio::{runtime::Handle, task::JoinHandle};
+
+use crate::Error;
+
+struct Task {
+    id: u64,
+    key: String,
+    description: String,
+    handle: JoinHandle<()>,
+}
+
+pub struct Finished<T> {
+    // Identifies what the task worked on, i.e. an instance name
+    pub key: String,
+    // What the task did, for use in messages, i.e. "start instance"
+    pub description: String,
+    pub result: Result<T, Error>,
+}
+
+// Runs futures on a tokio runtime so that the GUI's frame loop never blocks; results are collected with `poll`
+pub struct Tasks<T> {
+    handle: Handle,
+    sender: Sender<(u64, Result<T, Error>)>,
+    receiver: Receiver<(u64, Result<T, Error>)>,
+    running: Vec<Task>,
+    next_id: u64,
+}
+
+impl<T: Send + 'static> Tasks<T> {
+    pub fn new(handle: Handle) -> Self {
+        let (sender, receiver) = channel();
+
+        Self {
+            handle,
+            sender,
+            receiver,
+            running: vec![],
+            next_id: 0,
+        }
+    }
+
+    pub fn spawn<F>(self: &mut Self, key: &str, description: &str, future: F)
+    where
+        F: Future<Output = Result<T, Error>> + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let sender = self.sender.clone();
+        let handle = self.handle.spawn(async move {
+            // The receiver only goes away with the runner, at which point nobody is interested anymore
+            sender.send((id, future.await)).ok();
+        });
+
+        self.running.push(Task {
+            id,
+            key: key.to_owned(),
+            description: description.to_owned(),
+            handle,
+        });
+    }
+
+    // Returns the tasks which have finished since the last call
+    pub fn poll(self: &mut Self) -> Vec<Finished<T>> {
+        let mut finished = vec![];
+
+        while let Ok((id, result)) = self.receiver.try_recv() {
+            if let Some(i) = self.running.iter().position(|t| t.id == id) {
+                let task = self.running.remove(i);
+
+                finished.push(Finished {
+                    key: task.key,
+                    description: task.description,
+                    result,
+                });
+            }
+        }
+
+        finished
+    }
+
+    // Unlike tasks, subscriptions yield any number of items and stop when dropped
+    pub fn subscribe<U, F, Fut>(self: &Self, producer: F) -> Subscription<U>
+    where
+        U: Send + 'static,
+        F: FnOnce(Sender<U>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+
+        Subscription {
+            receiver,
+            handle: self.handle.spawn(producer(sender)),
+            closed: false,
+        }
+    }
+
+    pub fn is_running(self: &Self, key: &str) -> bool {
+        self.running.iter().any(|t| t.key == key)
+    }
+
+    pub fn is_idle(self: &Self) -> bool {
+        self.running.is_empty()
+    }
+
+    // Operations which have already reached the daemon might still complete, but their results are dropped
+    pub fn cancel(self: &mut Self, key: &str) {
+        self.running.retain(|t| {
+            if t.key == key {
+                t.handle.abort();
+
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl<T: Send + 'static> Default for Tasks<T> {
+    // Must be called from within a tokio runtime
+    fn default() -> Self {
+        Self::new(Handle::current())
+    }
+}
+
+// Items of a long-running producer, i.e. a log stream
+pub struct Subscription<T> {
+    receiver: Receiver<T>,
+    handle: JoinHandle<()>,
+    closed: bool,
+}
+
+impl<T> Subscription<T> {
+    // Returns the items which have been produced since the last call
+    pub fn poll(self: &mut Self) -> Vec<T> {
+        let mut items = vec![];
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(item) => items.push(item),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+
+                    break;
+                }
+            }
+        }
+
+        items
+    }
+
+    // Whether the producer has finished and all of its items have been polled
+    pub fn is_closed(self: &Self) -> bool {
+        self.closed
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
@@ -1,23 +1,67 @@
+mod create;
+mod logs;
+mod remove;
+mod terminal;
+
+use std::sync::Arc;
+
 use eframe::{
     egui::{self, Label},
     epi,
 };
-use futures::executor;
-use shiplift::Docker;
+use futures::StreamExt;
 use tokio::task::spawn_blocking;
 
 use crate::{
-    instances::{Instance, Instances},
+    instances::{
+        Instance, InstanceEvent, InstanceEventKind, InstanceHealth, InstanceStatus, Instances,
+        LifecycleAction, RemoveScope, Service,
+    },
+    tasks::{Subscription, Tasks},
     update::update,
+    Error,
 };
 
+use self::{create::CreateDialog, logs::LogViewer, remove::RemoveDialog, terminal::Terminal};
+
+// Task keys are namespaced so that instance names can't collide with the app's own tasks
+static REFRESH_TASK: &str = "app:refresh";
+static UPDATE_TASK: &str = "app:update";
+static ALLOCATE_TASK: &str = "app:allocate-ports";
+
+fn instance_task(name: &str) -> String {
+    format!("instance:{}", name)
+}
+
+// Results of background tasks
+enum Outcome {
+    Instances(Vec<Instance>),
+    Changed,
+    Updated(String),
+    // A free start port for the instance which is being created
+    Allocated(u64),
+    // The instance and the volumes which have been deleted with it
+    Removed(String, Vec<String>),
+}
+
+// Clicked buttons of an instance row, applied after the grid has been drawn
+enum Action {
+    Lifecycle(LifecycleAction),
+    Logs,
+    Open(String),
+    Enter,
+    Remove,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct SerializableInstance {
     pub name: String,
     pub start_port: Option<u64>,
     pub end_port: Option<u64>,
-    pub status: String,
+    pub status: InstanceStatus,
+    pub health: Option<InstanceHealth>,
+    pub services: Vec<Service>,
 }
 
 impl Default for SerializableInstance {
@@ -26,7 +70,9 @@ impl Default for SerializableInstance {
             name: "".to_owned(),
             start_port: Some(0),
             end_port: Some(0),
-            status: "".to_owned(),
+            status: InstanceStatus::Created,
+            health: None,
+            services: vec![],
         }
     }
 }
@@ -37,7 +83,9 @@ impl From<&Instance> for SerializableInstance {
             name: i.name.to_owned(),
             start_port: i.start_port,
             end_port: i.end_port,
-            status: i.status.to_owned(),
+            status: i.status,
+            health: i.health,
+            services: i.services(),
         }
     }
 }
@@ -48,9 +96,26 @@ pub struct Window {
     #[serde(skip)]
     instances: Vec<SerializableInstance>,
     #[serde(skip)]
-    refreshing: bool,
+    manager: Option<Arc<Instances>>,
+    #[serde(skip)]
+    tasks: Tasks<Outcome>,
+    #[serde(skip)]
+    events: Option<Subscription<Result<InstanceEvent, Error>>>,
+    // Wakes up the frame loop when events arrive, which would otherwise wait for input
+    #[serde(skip)]
+    repaint: Option<Arc<dyn epi::RepaintSignal>>,
+    #[serde(skip)]
+    error: Option<String>,
+    #[serde(skip)]
+    notice: Option<String>,
+    #[serde(skip)]
+    logs: Vec<LogViewer>,
+    #[serde(skip)]
+    terminals: Vec<Terminal>,
     #[serde(skip)]
-    manager: Option<Instances>,
+    remove_dialog: Option<RemoveDialog>,
+    #[serde(skip)]
+    create_dialog: Option<CreateDialog>,
 
     dark: bool,
 }
@@ -59,8 +124,16 @@ impl Default for Window {
     fn default() -> Self {
         Self {
             instances: vec![],
-            refreshing: false,
             manager: None,
+            tasks: Tasks::default(),
+            events: None,
+            repaint: None,
+            error: None,
+            notice: None,
+            logs: vec![],
+            terminals: vec![],
+            remove_dialog: None,
+            create_dialog: None,
 
             dark: true,
         }
@@ -75,10 +148,11 @@ impl epi::App for Window {
     fn setup(
         &mut self,
         _ctx: &egui::CtxRef,
-        _frame: &mut epi::Frame<'_>,
+        frame: &mut epi::Frame<'_>,
         storage: Option<&dyn epi::Storage>,
     ) {
-        *self = epi::get_value(storage.unwrap(), epi::APP_KEY).unwrap_or_default()
+        *self = epi::get_value(storage.unwrap(), epi::APP_KEY).unwrap_or_default();
+        self.repaint = Some(frame.repaint_signal());
     }
 
     fn save(&mut self, storage: &mut dyn epi::Storage) {
@@ -86,12 +160,23 @@ impl epi::App for Window {
     }
 
     fn update(&mut self, ctx: &egui::CtxRef, frame: &mut epi::Frame<'_>) {
+        self.poll_tasks();
+        self.poll_events();
+
+        // Keep polling until all tasks have reported back; streams request repaints themselves
+        if !self.tasks.is_idle() {
+            ctx.request_repaint();
+        }
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 egui::menu::menu(ui, "File", |ui| {
+                    if ui.button("New instance").clicked() {
+                        self.open_create_dialog();
+                    }
+
                     if ui.button("Refresh").clicked() {
-                        // TODO: Handle errors and run in background
-                        executor::block_on(self.refresh_instances()).unwrap();
+                        self.refresh_instances();
                     }
 
                     if ui.button("Quit").clicked() {
@@ -105,11 +190,14 @@ impl epi::App for Window {
                 });
 
                 egui::menu::menu(ui, "Help", |ui| {
-                    if ui.button("Check for updates").clicked() {
-                        // TODO: Handle errors and run in background
-                        executor::block_on(spawn_blocking(|| update()))
-                            .unwrap()
-                            .unwrap();
+                    if ui
+                        .add(
+                            egui::Button::new("Check for updates")
+                                .enabled(!self.tasks.is_running(UPDATE_TASK)),
+                        )
+                        .clicked()
+                    {
+                        self.check_for_updates();
                     }
                 });
             });
@@ -118,95 +206,449 @@ impl epi::App for Window {
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            if self.refreshing {
-                ui.heading("Refreshing ..");
+            if self.tasks.is_running(REFRESH_TASK) {
+                let mut cancelled = false;
+
+                ui.horizontal(|ui| {
+                    ui.heading("Refreshing ..");
+                    cancelled = ui.button("Cancel").clicked();
+                });
+
+                if cancelled {
+                    self.tasks.cancel(REFRESH_TASK);
+                }
             } else if self.instances.len() <= 0 {
                 ui.heading("No instances yet");
-                if ui.button("Connect to Docker").clicked() {
-                    // TODO: Handle errors and run in background
-                    executor::block_on(self.refresh_instances()).unwrap();
-                };
+                ui.horizontal(|ui| {
+                    if ui.button("Connect to Docker").clicked() {
+                        self.refresh_instances();
+                    };
+
+                    if ui.button("New instance").clicked() {
+                        self.open_create_dialog();
+                    }
+                });
+            }
+
+            if let Some(error) = &self.error {
+                let mut dismissed = false;
+
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::RED, error);
+                    dismissed = ui.button("Dismiss").clicked();
+                });
+
+                if dismissed {
+                    self.error = None;
+                }
+            }
+
+            if let Some(notice) = &self.notice {
+                let mut dismissed = false;
+
+                ui.horizontal(|ui| {
+                    ui.label(notice);
+                    dismissed = ui.button("Dismiss").clicked();
+                });
+
+                if dismissed {
+                    self.notice = None;
+                }
             }
 
             if self.instances.len() > 0 {
+                let mut actions = vec![];
+                let mut cancelled = vec![];
+
                 egui::Grid::new("instances").striped(true).show(ui, |ui| {
                     ui.add(Label::new("Name").strong());
                     ui.add(Label::new("Status").strong());
                     ui.add(Label::new("Ports").strong());
+                    ui.add(Label::new("Services").strong());
                     ui.add(Label::new("Actions").strong());
 
                     ui.end_row();
 
                     self.instances.iter().for_each(|i| {
+                        ui.label(i.name.to_owned());
+                        match i.health {
+                            Some(health) => ui.label(format!("{} ({})", i.status, health)),
+                            None => ui.label(i.status.to_string()),
+                        };
+
                         if let (Some(start_port), Some(end_port)) = (i.start_port, i.end_port) {
-                            ui.label(i.name.to_owned());
-                            ui.label(i.status.to_owned());
                             ui.monospace(start_port.to_string() + "-" + &end_port.to_string());
-
-                            ui.horizontal(|ui| {
-                                if ui.button("Stop").clicked() {
-                                    executor::block_on(self.stop_instance(&i.name.to_owned()))
-                                        .unwrap();
-                                }
-                            });
                         } else {
-                            ui.label(i.name.to_owned());
-                            ui.label(i.status.to_owned());
                             ui.monospace("");
                         }
 
+                        ui.horizontal(|ui| {
+                            for s in &i.services {
+                                ui.hyperlink_to(&s.title, &s.url)
+                                    .on_hover_text(format!("{} on port {}", s.title, s.port));
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            // Only one operation per instance can be in flight
+                            if self.tasks.is_running(&instance_task(&i.name)) {
+                                ui.label("Working ..");
+
+                                if ui.button("Cancel").clicked() {
+                                    cancelled.push(i.name.to_owned());
+                                }
+
+                                return;
+                            }
+
+                            let running = i.status == InstanceStatus::Running;
+
+                            let popup = ui.make_persistent_id(("open", &i.name));
+                            let open = ui.add(
+                                egui::Button::new("Open")
+                                    .enabled(running && !i.services.is_empty()),
+                            );
+                            if open.clicked() {
+                                ui.memory().toggle_popup(popup);
+                            }
+                            egui::popup::popup_below_widget(ui, popup, &open, |ui| {
+                                for s in &i.services {
+                                    if ui.button(&s.title).on_hover_text(&s.url).clicked() {
+                                        actions.push((
+                                            i.name.to_owned(),
+                                            Action::Open(s.url.to_owned()),
+                                        ));
+                                    }
+                                }
+                            });
+
+                            let mut button = |text: &str, enabled: bool, action: Action| {
+                                if ui.add(egui::Button::new(text).enabled(enabled)).clicked() {
+                                    actions.push((i.name.to_owned(), action));
+                                }
+                            };
+
+                            button(
+                                "Start",
+                                i.status.can_start(),
+                                Action::Lifecycle(LifecycleAction::Start),
+                            );
+                            button(
+                                "Stop",
+                                i.status.can_stop(),
+                                Action::Lifecycle(LifecycleAction::Stop),
+                            );
+                            button(
+                                "Restart",
+                                i.status.can_restart(),
+                                Action::Lifecycle(LifecycleAction::Restart),
+                            );
+                            button("Logs", true, Action::Logs);
+
+                            button("Enter", running, Action::Enter);
+                            button(
+                                "Remove",
+                                i.status != InstanceStatus::Removing,
+                                Action::Remove,
+                            );
+                        });
+
                         ui.end_row();
                     });
                 });
+
+                cancelled
+                    .iter()
+                    .for_each(|name| self.tasks.cancel(&instance_task(name)));
+                actions
+                    .into_iter()
+                    .for_each(|(name, action)| self.apply_action(ui, &name, action));
             }
 
             egui::warn_if_debug_build(ui);
         });
+
+        self.show_windows(ctx);
     }
 }
 
 impl Window {
-    async fn refresh_instances(&mut self) -> Result<(), ()> {
-        let mut s = scopeguard::guard(self, |r| {
-            r.refreshing = false;
-        });
+    fn manager(&mut self) -> Arc<Instances> {
+        self.manager
+            .get_or_insert_with(|| Arc::new(Instances::default()))
+            .clone()
+    }
 
-        s.refreshing = true;
+    fn poll_tasks(&mut self) {
+        for task in self.tasks.poll() {
+            match task.result {
+                Ok(Outcome::Instances(instances)) => {
+                    self.instances = instances
+                        .iter()
+                        .map(|i| SerializableInstance::from(i))
+                        .collect::<Vec<_>>()
+                }
+                Ok(Outcome::Changed) => self.refresh_instances(),
+                Ok(Outcome::Updated(version)) => {
+                    self.notice = Some(format!("Upgrade status: `{}`", version))
+                }
+                Ok(Outcome::Allocated(start_port)) => {
+                    if let Some(dialog) = &mut self.create_dialog {
+                        dialog.suggest_start_port(start_port);
+                    }
+                }
+                Ok(Outcome::Removed(name, volumes)) => {
+                    self.notice = Some(if volumes.is_empty() {
+                        format!("Removed instance {:?}", name)
+                    } else {
+                        format!(
+                            "Removed instance {:?} and volumes {}",
+                            name,
+                            volumes.join(", ")
+                        )
+                    });
+                    self.refresh_instances();
+                }
+                Err(e) => self.error = Some(format!("Could not {}: {}", task.description, e)),
+            }
+        }
+    }
 
-        let manager = match &mut s.manager {
-            Some(m) => m,
-            None => {
-                s.manager = Some(Instances {
-                    docker: Docker::new(),
-                });
+    // Applies changes which don't need another listing directly, so that the grid updates immediately
+    fn poll_events(&mut self) {
+        let (events, closed) = match &mut self.events {
+            Some(subscription) => (subscription.poll(), subscription.is_closed()),
+            None => return,
+        };
 
-                s.manager.as_ref().unwrap()
+        // Refreshing resumes watching
+        if closed {
+            self.events = None;
+        }
+
+        let mut refresh = false;
+
+        for event in events {
+            match event {
+                Ok(event) => refresh |= self.apply_event(&event),
+                Err(e) => self.error = Some(format!("Could not watch instances: {}", e)),
             }
-        };
+        }
 
-        println!("Refreshing ...");
+        if refresh {
+            self.refresh_instances();
+        }
+    }
 
-        match manager.get_instances().await {
-            Ok(containers) => {
-                s.instances = containers
-                    .iter()
-                    .map(|i| SerializableInstance::from(i))
-                    .collect::<Vec<_>>()
+    // Returns whether the instances must be listed again
+    fn apply_event(&mut self, event: &InstanceEvent) -> bool {
+        if event.kind == InstanceEventKind::Removed {
+            self.instances.retain(|i| i.name != event.name);
+
+            return false;
+        }
+
+        match (
+            &event.kind,
+            self.instances.iter_mut().find(|i| i.name == event.name),
+        ) {
+            (InstanceEventKind::Died(code), Some(i)) => {
+                i.status = InstanceStatus::Exited(code.unwrap_or(0));
+                i.health = None;
+
+                false
+            }
+            (InstanceEventKind::HealthChanged(health), Some(i)) => {
+                i.health = Some(*health);
+
+                false
+            }
+            // Volumes aren't shown
+            (InstanceEventKind::VolumeCreated(_), _) | (InstanceEventKind::VolumeRemoved(_), _) => {
+                false
+            }
+            // Ports and uptime of new and started instances are only known to the daemon
+            _ => true,
+        }
+    }
+
+    fn watch(&mut self) {
+        let manager = self.manager();
+        let repaint = self.repaint.clone();
+
+        self.events = Some(self.tasks.subscribe(move |sender| async move {
+            let mut events = manager.watch();
+
+            while let Some(event) = events.next().await {
+                if sender.send(event).is_err() {
+                    break;
+                }
+
+                if let Some(repaint) = &repaint {
+                    repaint.request_repaint();
+                }
+            }
+        }));
+    }
+
+    fn refresh_instances(&mut self) {
+        // A newer refresh supersedes the running one
+        self.tasks.cancel(REFRESH_TASK);
+
+        // Watching starts once the user has connected, and resumes if the stream broke
+        if self.events.is_none() {
+            self.watch();
+        }
+
+        let manager = self.manager();
+
+        self.tasks
+            .spawn(REFRESH_TASK, "list instances", async move {
+                Ok(Outcome::Instances(manager.get_instances().await?))
+            });
+    }
+
+    fn apply_action(&mut self, ui: &mut egui::Ui, name: &str, action: Action) {
+        match action {
+            Action::Lifecycle(action) => self.run_action(name, action),
+            Action::Logs => {
+                if !self.logs.iter().any(|l| l.name() == name) {
+                    let viewer =
+                        LogViewer::new(&self.tasks, self.manager(), self.repaint.clone(), name);
+
+                    self.logs.push(viewer);
+                }
+            }
+            Action::Open(url) => ui.ctx().output().open_url(url),
+            Action::Enter => {
+                if !self.terminals.iter().any(|t| t.name() == name) {
+                    let terminal =
+                        Terminal::new(&self.tasks, self.manager(), self.repaint.clone(), name);
+
+                    self.terminals.push(terminal);
+                }
+            }
+            Action::Remove => {
+                let others = self.instances.iter().any(|i| i.name != name);
+
+                self.remove_dialog = Some(RemoveDialog::new(name, others));
+            }
+        }
+    }
+
+    fn show_windows(&mut self, ctx: &egui::CtxRef) {
+        self.logs.iter_mut().for_each(|l| l.show(ctx));
+        self.logs.retain(|l| l.is_open());
+
+        self.terminals.iter_mut().for_each(|t| t.show(ctx));
+        self.terminals.retain(|t| t.is_open());
+
+        if let Some(dialog) = &mut self.remove_dialog {
+            match dialog.show(ctx) {
+                remove::Decision::Undecided => {}
+                remove::Decision::Cancel => self.remove_dialog = None,
+                remove::Decision::Remove(scope) => {
+                    let name = dialog.name().to_owned();
+
+                    self.remove_dialog = None;
+                    self.remove_instance(&name, scope);
+                }
             }
-            Err(e) => eprintln!("Could not list instances: {}", e),
         }
 
-        Ok(())
+        self.show_create_dialog(ctx);
+    }
+
+    fn open_create_dialog(&mut self) {
+        let manager = self.manager();
+
+        self.create_dialog = Some(CreateDialog::new());
+
+        self.tasks.cancel(ALLOCATE_TASK);
+        self.tasks
+            .spawn(ALLOCATE_TASK, "find free ports", async move {
+                Ok(Outcome::Allocated(manager.next_free_ports().await?))
+            });
     }
 
-    async fn stop_instance(&self, name: &str) -> Result<(), ()> {
-        // TODO: Upsert manager by calling `refresh_instances`
-        match self.manager.as_ref().unwrap().stop(name).await {
-            Ok(_) => {}
-            Err(e) => eprintln!("Could not stop instance: {}", e),
+    fn show_create_dialog(&mut self, ctx: &egui::CtxRef) {
+        if self.create_dialog.is_none() {
+            return;
         }
 
-        Ok(())
+        let manager = self.manager();
+
+        let decision = match &mut self.create_dialog {
+            Some(dialog) => dialog.show(
+                ctx,
+                &self.instances,
+                &self.tasks,
+                manager,
+                self.repaint.clone(),
+            ),
+            None => return,
+        };
+
+        match decision {
+            create::Decision::Undecided => {}
+            create::Decision::Cancel => self.create_dialog = None,
+            create::Decision::Created(name) => {
+                self.create_dialog = None;
+                self.notice = Some(format!("Created instance {:?}", name));
+                self.refresh_instances();
+            }
+        }
+    }
+
+    fn remove_instance(&mut self, name: &str, scope: RemoveScope) {
+        // Streams of the removed container would only report errors
+        self.logs.retain(|l| l.name() != name);
+        self.terminals.retain(|t| t.name() != name);
+
+        let manager = self.manager();
+        let owned_name = name.to_owned();
+
+        self.tasks.spawn(
+            &instance_task(name),
+            &format!("remove instance {:?}", name),
+            async move {
+                let volumes = manager.remove(&[owned_name.clone()], &scope).await?;
+
+                Ok(Outcome::Removed(owned_name, volumes))
+            },
+        );
+    }
+
+    fn run_action(&mut self, name: &str, action: LifecycleAction) {
+        let manager = self.manager();
+        let owned_name = name.to_owned();
+
+        self.tasks.spawn(
+            &instance_task(name),
+            &format!("{} instance {:?}", action, name),
+            async move {
+                manager.run(&owned_name, action).await?;
+
+                Ok(Outcome::Changed)
+            },
+        );
+    }
+
+    fn check_for_updates(&mut self) {
+        self.tasks
+            .spawn(UPDATE_TASK, "check for updates", async move {
+                match spawn_blocking(update).await {
+                    Ok(Ok(status)) => Ok(Outcome::Updated(status.version().to_owned())),
+                    Ok(Err(e)) => Err(Error::Other {
+                        name: UPDATE_TASK.to_owned(),
+                        cause: e.into(),
+                    }),
+                    Err(e) => Err(Error::Other {
+                        name: UPDATE_TASK.to_owned(),
+                        cause: e.into(),
+                    }),
+                }
+            });
     }
 
     fn update_dark_mode(&mut self, ui: &mut egui::Ui) {
@@ -0,0 +1,281 @@
+use std::{collections::HashMap, sync::Arc};
+
+use eframe::{egui, epi};
+use futures::StreamExt;
+
+use crate::{
+    backend::PullProgress,
+    instances::{
+        port_range, validate_name, validate_start_port, ApplyOptions, Instances, POJDE_MODULES,
+    },
+    tasks::{Subscription, Tasks},
+    Error,
+};
+
+use super::SerializableInstance;
+
+enum Step {
+    Pulling(PullProgress),
+    Applying,
+    Done(Result<(), Error>),
+}
+
+pub enum Decision {
+    Undecided,
+    Created(String),
+    Cancel,
+}
+
+// Collects the options of `pojdectl modify apply` and applies them
+pub struct CreateDialog {
+    name: String,
+    start_port: String,
+    upgrade: bool,
+    isolate: bool,
+    privileged: bool,
+    modules: Vec<bool>,
+    error: Option<String>,
+    // Set while applying
+    progress: Option<Subscription<Step>>,
+    // Downloaded and total bytes by layer
+    layers: HashMap<String, (u64, u64)>,
+    status: String,
+}
+
+impl CreateDialog {
+    // The start port is suggested once free ports have been found
+    pub fn new() -> Self {
+        Self {
+            name: String::new(),
+            start_port: String::new(),
+            upgrade: false,
+            isolate: false,
+            privileged: false,
+            modules: vec![false; POJDE_MODULES.len()],
+            error: None,
+            progress: None,
+            layers: HashMap::new(),
+            status: String::new(),
+        }
+    }
+
+    // Keeps ports which have been entered in the meantime
+    pub fn suggest_start_port(self: &mut Self, start_port: u64) {
+        if self.start_port.is_empty() {
+            self.start_port = start_port.to_string();
+        }
+    }
+
+    pub fn show<T: Send + 'static>(
+        self: &mut Self,
+        ctx: &egui::CtxRef,
+        existing: &[SerializableInstance],
+        tasks: &Tasks<T>,
+        manager: Arc<Instances>,
+        repaint: Option<Arc<dyn epi::RepaintSignal>>,
+    ) -> Decision {
+        let mut decision = self.poll();
+        let validation = self.validate(existing);
+
+        egui::Window::new("New instance")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if self.progress.is_some() {
+                    self.show_progress(ui);
+
+                    return;
+                }
+
+                egui::Grid::new("create").num_columns(2).show(ui, |ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut self.name);
+                    ui.end_row();
+
+                    ui.label("Start port");
+                    ui.text_edit_singleline(&mut self.start_port)
+                        .on_hover_text("The instance uses this and the following six ports");
+                    ui.end_row();
+                });
+
+                ui.checkbox(&mut self.upgrade, "Pull latest image");
+                ui.checkbox(&mut self.isolate, "Block Docker daemon access");
+                ui.checkbox(&mut self.privileged, "Run in privileged mode");
+
+                ui.separator();
+
+                ui.label("Language modules");
+                ui.horizontal_wrapped(|ui| {
+                    for (selected, (_, title)) in self.modules.iter_mut().zip(POJDE_MODULES.iter())
+                    {
+                        ui.checkbox(selected, *title);
+                    }
+                });
+
+                ui.separator();
+
+                if let Err(e) = &validation {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+                if let Some(e) = &self.error {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add(egui::Button::new("Create").enabled(validation.is_ok()))
+                        .clicked()
+                    {
+                        if let Ok(start_port) = validation {
+                            self.apply(tasks, manager, repaint, start_port);
+                        }
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        decision = Decision::Cancel;
+                    }
+                });
+            });
+
+        decision
+    }
+
+    // Returns the start port if the options are valid
+    fn validate(self: &Self, existing: &[SerializableInstance]) -> Result<u64, String> {
+        validate_name(&self.name)?;
+
+        if existing.iter().any(|i| i.name == self.name) {
+            return Err(format!("instance {:?} already exists", self.name));
+        }
+
+        // Until free ports have been found
+        if self.start_port.is_empty() {
+            return Err("enter a start port".to_owned());
+        }
+
+        let start_port = self
+            .start_port
+            .parse::<u64>()
+            .map_err(|_| format!("invalid start port {:?}", self.start_port))?;
+        validate_start_port(start_port)?;
+        let (start, end) = port_range(start_port);
+
+        if let Some(i) = existing.iter().find(|i| match (i.start_port, i.end_port) {
+            (Some(other_start), Some(other_end)) => start <= other_end && other_start <= end,
+            _ => false,
+        }) {
+            return Err(format!(
+                "ports {}-{} overlap with instance {:?}",
+                start, end, i.name
+            ));
+        }
+
+        Ok(start_port)
+    }
+
+    fn apply<T: Send + 'static>(
+        self: &mut Self,
+        tasks: &Tasks<T>,
+        manager: Arc<Instances>,
+        repaint: Option<Arc<dyn epi::RepaintSignal>>,
+        start_port: u64,
+    ) {
+        let name = self.name.to_owned();
+        let options = ApplyOptions {
+            start_port,
+            // The image has been pulled already
+            upgrade: false,
+            recreate: false,
+            isolate: self.isolate,
+            privileged: self.privileged,
+            modules: POJDE_MODULES
+                .iter()
+                .zip(self.modules.iter())
+                .filter(|(_, selected)| **selected)
+                .map(|((id, _), _)| id.to_string())
+                .collect(),
+            ..ApplyOptions::default()
+        };
+        let upgrade = self.upgrade;
+
+        self.error = None;
+        self.layers.clear();
+        self.status = "Checking image".to_owned();
+
+        // Pulls separately from applying so that its progress can be shown
+        self.progress = Some(tasks.subscribe(move |sender| async move {
+            let send = |step| {
+                sender.send(step).ok();
+
+                if let Some(repaint) = &repaint {
+                    repaint.request_repaint();
+                }
+            };
+
+            let mut progress = manager.pull_image(&name, upgrade);
+
+            while let Some(p) = progress.next().await {
+                match p {
+                    Ok(p) => send(Step::Pulling(p)),
+                    Err(e) => {
+                        send(Step::Done(Err(e)));
+
+                        return;
+                    }
+                };
+            }
+
+            send(Step::Applying);
+            send(Step::Done(manager.apply(&name, &options).await.map(|_| ())));
+        }));
+    }
+
+    fn poll(self: &mut Self) -> Decision {
+        let steps = match &mut self.progress {
+            Some(progress) => progress.poll(),
+            None => return Decision::Undecided,
+        };
+
+        for step in steps {
+            match step {
+                Step::Pulling(p) => {
+                    if let (Some(id), Some(current), Some(total)) = (&p.id, p.current, p.total) {
+                        self.layers.insert(id.to_owned(), (current, total));
+                    }
+
+                    self.status = match &p.id {
+                        Some(id) => format!("{} {}", p.status, id),
+                        None => p.status,
+                    };
+                }
+                Step::Applying => self.status = "Creating and starting instance".to_owned(),
+                Step::Done(Ok(_)) => return Decision::Created(self.name.to_owned()),
+                Step::Done(Err(e)) => {
+                    self.progress = None;
+                    self.error = Some(format!("Could not create instance: {}", e));
+                }
+            }
+        }
+
+        Decision::Undecided
+    }
+
+    fn show_progress(self: &Self, ui: &mut egui::Ui) {
+        ui.label(format!("Creating instance {:?}", self.name));
+
+        if !self.layers.is_empty() {
+            let (current, total) = self
+                .layers
+                .values()
+                .fold((0, 0), |(c, t), (current, total)| (c + current, t + total));
+
+            ui.add(
+                egui::ProgressBar::new(current as f32 / total.max(1) as f32)
+                    .show_percentage()
+                    .animate(true),
+            );
+        }
+
+        ui.label(&self.status);
+    }
+}
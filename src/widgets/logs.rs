@@ -0,0 +1,249 @@
+use std::{fs, sync::Arc};
+
+use eframe::{egui, epi};
+use futures::StreamExt;
+
+use crate::{
+    backend::{Chunk, LogQuery},
+    instances::Instances,
+    tasks::{Subscription, Tasks},
+    Error,
+};
+
+// Older lines are available with `pojdectl util logs`
+static TAIL: u64 = 1000;
+// Lines beyond this are dropped from the top so that long-running instances don't exhaust memory
+static MAX_LINES: usize = 10000;
+// Every line takes up one row of this style, so that only the visible ones have to be drawn
+static TEXT_STYLE: egui::TextStyle = egui::TextStyle::Monospace;
+
+struct Line {
+    stderr: bool,
+    text: String,
+}
+
+pub struct LogViewer {
+    name: String,
+    lines: Vec<Line>,
+    error: Option<String>,
+    notice: Option<String>,
+    logs: Subscription<Result<Chunk, Error>>,
+    search: String,
+    autoscroll: bool,
+    path: String,
+    open: bool,
+}
+
+impl LogViewer {
+    pub fn new<T: Send + 'static>(
+        tasks: &Tasks<T>,
+        manager: Arc<Instances>,
+        repaint: Option<Arc<dyn epi::RepaintSignal>>,
+        name: &str,
+    ) -> Self {
+        let owned_name = name.to_owned();
+
+        // Same stream as `pojdectl util logs --follow --tail`
+        let logs = tasks.subscribe(move |sender| async move {
+            let query = LogQuery {
+                follow: true,
+                tail: Some(TAIL),
+                ..Default::default()
+            };
+            let mut logs = manager.get_logs(&owned_name, &query).await;
+
+            while let Some(chunk) = logs.next().await {
+                if sender.send(chunk).is_err() {
+                    break;
+                }
+
+                if let Some(repaint) = &repaint {
+                    repaint.request_repaint();
+                }
+            }
+
+            // Shows that the stream has ended
+            if let Some(repaint) = &repaint {
+                repaint.request_repaint();
+            }
+        });
+
+        Self {
+            name: name.to_owned(),
+            lines: vec![],
+            error: None,
+            notice: None,
+            logs,
+            search: String::new(),
+            autoscroll: true,
+            path: format!("{}.log", name),
+            open: true,
+        }
+    }
+
+    pub fn name(self: &Self) -> &str {
+        &self.name
+    }
+
+    pub fn is_open(self: &Self) -> bool {
+        self.open
+    }
+
+    pub fn show(self: &mut Self, ctx: &egui::CtxRef) {
+        for chunk in self.logs.poll() {
+            match chunk {
+                Ok(Chunk::StdOut(b)) => self.push(false, &b),
+                Ok(Chunk::StdErr(b)) => self.push(true, &b),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+
+        let mut open = self.open;
+        let mut save = false;
+
+        egui::Window::new(format!("Logs of {}", self.name))
+            .id(egui::Id::new(("logs", &self.name)))
+            .open(&mut open)
+            .resizable(true)
+            .default_size([720.0, 480.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Search:");
+                    ui.text_edit_singleline(&mut self.search);
+
+                    if !self.search.is_empty() {
+                        let matches = self
+                            .lines
+                            .iter()
+                            .filter(|l| l.text.contains(&self.search))
+                            .count();
+
+                        ui.label(format!("{} matching lines", matches));
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.autoscroll, "Autoscroll")
+                        .on_hover_text("Uncheck to pause scrolling while reading");
+
+                    ui.separator();
+
+                    ui.text_edit_singleline(&mut self.path);
+                    save = ui.button("Save to file").clicked();
+                });
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                } else if self.logs.is_closed() {
+                    ui.label("Log stream ended");
+                }
+
+                if let Some(notice) = &self.notice {
+                    ui.label(notice);
+                }
+
+                ui.separator();
+
+                let lines = &self.lines;
+                let search = &self.search;
+                let autoscroll = self.autoscroll;
+                let row_height = ui.fonts()[TEXT_STYLE].row_height();
+
+                egui::ScrollArea::auto_sized().show_rows(
+                    ui,
+                    row_height,
+                    lines.len(),
+                    |ui, rows| {
+                        let below = lines.len() - rows.end;
+
+                        for line in &lines[rows] {
+                            show_line(ui, line, search);
+                        }
+
+                        if autoscroll {
+                            // Move past the rows which aren't drawn
+                            ui.add_space(below as f32 * (row_height + ui.spacing().item_spacing.y));
+                            ui.scroll_to_cursor(egui::Align::BOTTOM);
+                        }
+                    },
+                );
+            });
+
+        self.open = open;
+
+        if save {
+            self.save();
+        }
+    }
+
+    fn push(self: &mut Self, stderr: bool, b: &[u8]) {
+        // Docker splits chunks at line boundaries
+        self.lines
+            .extend(String::from_utf8_lossy(b).lines().map(|text| Line {
+                stderr,
+                text: text.to_owned(),
+            }));
+
+        if self.lines.len() > MAX_LINES {
+            self.lines.drain(..self.lines.len() - MAX_LINES);
+        }
+    }
+
+    fn save(self: &mut Self) {
+        let mut content = self
+            .lines
+            .iter()
+            .map(|l| l.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        content.push('\n');
+
+        match fs::write(&self.path, content) {
+            Ok(_) => {
+                self.notice = Some(format!("Saved {} lines to {}", self.lines.len(), self.path))
+            }
+            Err(e) => self.error = Some(format!("Could not save logs: {}", e)),
+        }
+    }
+}
+
+// Splits a line into segments which are highlighted if they match the search
+fn show_line(ui: &mut egui::Ui, line: &Line, search: &str) {
+    let color = if line.stderr {
+        egui::Color32::LIGHT_RED
+    } else {
+        ui.visuals().text_color()
+    };
+    let label = |text: &str| {
+        egui::Label::new(text)
+            .text_style(TEXT_STYLE)
+            .text_color(color)
+            .wrap(false)
+    };
+
+    if search.is_empty() || !line.text.contains(search) {
+        ui.add(label(&line.text));
+
+        return;
+    }
+
+    // Unlike `horizontal`, this doesn't make the row taller than the others
+    let size = egui::vec2(ui.available_width(), ui.fonts()[TEXT_STYLE].row_height());
+    ui.allocate_ui_with_layout(size, egui::Layout::left_to_right(), |ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+
+        let mut rest = line.text.as_str();
+        while let Some(i) = rest.find(search) {
+            ui.add(label(&rest[..i]));
+            ui.add(
+                label(search)
+                    .text_color(egui::Color32::BLACK)
+                    .background_color(egui::Color32::YELLOW),
+            );
+
+            rest = &rest[i + search.len()..];
+        }
+
+        ui.add(label(rest));
+    });
+}
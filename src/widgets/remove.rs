@@ -0,0 +1,97 @@
+use eframe::egui;
+
+use crate::instances::{container_name, RemoveScope};
+
+pub enum Decision {
+    Undecided,
+    Remove(RemoveScope),
+    Cancel,
+}
+
+// Mirrors the flags of `pojdectl remove`
+pub struct RemoveDialog {
+    name: String,
+    scope: RemoveScope,
+    all: bool,
+    // Whether other instances exist, which keeps the shared CA
+    others: bool,
+}
+
+impl RemoveDialog {
+    pub fn new(name: &str, others: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            scope: RemoveScope::default(),
+            all: false,
+            others,
+        }
+    }
+
+    pub fn name(self: &Self) -> &str {
+        &self.name
+    }
+
+    pub fn show(self: &mut Self, ctx: &egui::CtxRef) -> Decision {
+        let mut decision = Decision::Undecided;
+        let title = format!("Remove instance {:?}?", self.name);
+
+        egui::Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("The container is always removed. Also remove:");
+
+                ui.checkbox(&mut self.scope.customizations, "Customizations");
+                ui.checkbox(&mut self.scope.preferences, "Preferences");
+                ui.checkbox(&mut self.scope.security, "CA")
+                    .on_hover_text("The CA is shared by all instances");
+                ui.checkbox(&mut self.scope.user_data, "User data");
+                ui.checkbox(&mut self.scope.transfer, "Transfer data");
+                ui.checkbox(&mut self.scope.deb_cache, ".deb cache");
+                ui.checkbox(&mut self.all, "Everything");
+
+                // Like `--all`, this overrides the individual choices
+                if self.all {
+                    self.scope = RemoveScope::all();
+                }
+
+                ui.separator();
+
+                let names = [self.name.to_owned()];
+                let volumes = self.scope.removable_volumes(&names, self.others);
+
+                ui.label("This will permanently delete:");
+                ui.monospace(container_name(&self.name));
+                for volume in &volumes {
+                    ui.monospace(volume);
+                }
+
+                for volume in self
+                    .scope
+                    .volumes(&names)
+                    .iter()
+                    .filter(|v| !volumes.contains(v))
+                {
+                    ui.label(format!(
+                        "{} is kept, as other instances still use it.",
+                        volume
+                    ));
+                }
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add(egui::Button::new("Remove").text_color(egui::Color32::RED))
+                        .clicked()
+                    {
+                        decision = Decision::Remove(self.scope.clone());
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        decision = Decision::Cancel;
+                    }
+                });
+            });
+
+        decision
+    }
+}
@@ -0,0 +1,456 @@
+use std::sync::Arc;
+
+use eframe::{
+    egui::{self, Color32, Key, Modifiers, Pos2, Rect, Sense, TextStyle, Vec2},
+    epi,
+};
+use futures::StreamExt;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+};
+
+use crate::{
+    backend::{Chunk, ExecSession},
+    instances::Instances,
+    screen::{Color, Screen},
+    tasks::{Subscription, Tasks},
+    Error,
+};
+
+// Size of the session until the window has been laid out
+static INITIAL_SIZE: (usize, usize) = (80, 24);
+
+// The xterm palette of the 16 ANSI colors
+static ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+enum Input {
+    Bytes(Vec<u8>),
+    Resize(u16, u16),
+}
+
+// A terminal emulator for the login shell of an instance
+pub struct Terminal {
+    name: String,
+    screen: Screen,
+    error: Option<String>,
+    input: UnboundedSender<Input>,
+    session: Subscription<Result<Vec<u8>, Error>>,
+    // Keyboard input is only sent while the terminal has been clicked last
+    focused: bool,
+    selection: Option<((usize, usize), (usize, usize))>,
+    open: bool,
+}
+
+impl Terminal {
+    pub fn new<T: Send + 'static>(
+        tasks: &Tasks<T>,
+        manager: Arc<Instances>,
+        repaint: Option<Arc<dyn epi::RepaintSignal>>,
+        name: &str,
+    ) -> Self {
+        let owned_name = name.to_owned();
+        let (input, mut input_receiver) = unbounded_channel::<Input>();
+
+        let session = tasks.subscribe(move |sender| async move {
+            // The window only redraws on input otherwise
+            let repaint = || {
+                if let Some(repaint) = &repaint {
+                    repaint.request_repaint();
+                }
+            };
+
+            let ExecSession {
+                id,
+                mut output,
+                input: mut stdin,
+            } = match manager.enter(&owned_name, None).await {
+                Ok(session) => session,
+                Err(e) => {
+                    sender.send(Err(e)).ok();
+                    repaint();
+
+                    return;
+                }
+            };
+
+            let upstream = async {
+                while let Some(input) = input_receiver.recv().await {
+                    let res = match input {
+                        Input::Bytes(b) => stdin.write_all(&b).await.map_err(|_| ()),
+                        Input::Resize(cols, rows) => {
+                            manager.resize(&id, cols, rows).await.map_err(|_| ())
+                        }
+                    };
+
+                    if res.is_err() {
+                        break;
+                    }
+                }
+            };
+
+            let downstream = async {
+                while let Some(chunk) = output.next().await {
+                    let b = chunk.map(|c| match c {
+                        Chunk::StdOut(b) | Chunk::StdErr(b) => b,
+                    });
+
+                    if sender.send(b).is_err() {
+                        break;
+                    }
+
+                    repaint();
+                }
+            };
+
+            // The shell exiting ends the session; the window closing drops the subscription
+            tokio::select! {
+                _ = upstream => {},
+                _ = downstream => {},
+            }
+
+            repaint();
+        });
+
+        Self {
+            name: name.to_owned(),
+            screen: Screen::new(INITIAL_SIZE.0, INITIAL_SIZE.1),
+            error: None,
+            input,
+            session,
+            focused: true,
+            selection: None,
+            open: true,
+        }
+    }
+
+    pub fn name(self: &Self) -> &str {
+        &self.name
+    }
+
+    pub fn is_open(self: &Self) -> bool {
+        self.open
+    }
+
+    pub fn show(self: &mut Self, ctx: &egui::CtxRef) {
+        for b in self.session.poll() {
+            match b {
+                Ok(b) => self.screen.process(&b),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+
+        // Answers to queries of the shell, i.e. for the cursor position
+        let responses = self.screen.take_responses();
+        if !responses.is_empty() {
+            self.send(Input::Bytes(responses));
+        }
+
+        let title = match self.screen.title() {
+            "" => format!("Terminal of {}", self.name),
+            title => format!("{} ({})", title, self.name),
+        };
+        let mut open = self.open;
+
+        egui::Window::new(title)
+            .id(egui::Id::new(("terminal", &self.name)))
+            .open(&mut open)
+            .resizable(true)
+            .default_size([720.0, 440.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Copy")
+                        .on_hover_text("Copy the selection or the whole screen (Ctrl+Shift+C)")
+                        .clicked()
+                    {
+                        self.copy(ui);
+                    }
+
+                    if let Some(error) = &self.error {
+                        ui.colored_label(Color32::RED, error);
+                    } else if self.session.is_closed() {
+                        ui.label("Session ended");
+                    }
+                });
+
+                self.show_screen(ui);
+            });
+
+        self.open = open;
+    }
+
+    fn send(self: &Self, input: Input) {
+        // The session might already have ended, in which case there is nobody to read the input
+        self.input.send(input).ok();
+    }
+
+    fn copy(self: &Self, ui: &egui::Ui) {
+        let text = match self.selection {
+            Some((start, end)) => self.screen.text(start, end),
+            None => self.screen.contents(),
+        };
+
+        ui.ctx().output().copied_text = text;
+    }
+
+    fn show_screen(self: &mut Self, ui: &mut egui::Ui) {
+        let font = &ui.fonts()[TextStyle::Monospace];
+        let cell = Vec2::new(font.glyph_width('m'), font.row_height());
+
+        // Fill the window with as many cells as fit
+        let available = ui.available_size();
+        let size = (
+            ((available.x / cell.x) as usize).max(1),
+            ((available.y / cell.y) as usize).max(1),
+        );
+
+        if size != self.screen.size() {
+            self.screen.resize(size.0, size.1);
+            self.selection = None;
+            self.send(Input::Resize(size.0 as u16, size.1 as u16));
+        }
+
+        let (rect, response) = ui.allocate_exact_size(
+            Vec2::new(size.0 as f32 * cell.x, size.1 as f32 * cell.y),
+            Sense::click_and_drag(),
+        );
+
+        let position = |pos: Pos2| {
+            let offset = pos - rect.min;
+
+            (
+                ((offset.y / cell.y).max(0.0) as usize).min(size.1 - 1),
+                ((offset.x / cell.x).max(0.0) as usize).min(size.0 - 1),
+            )
+        };
+
+        if response.clicked() {
+            self.focused = true;
+            self.selection = None;
+        } else if ui.input().pointer.any_click() && !response.hovered() {
+            self.focused = false;
+        }
+
+        if response.drag_started() {
+            if let Some(pos) = ui.input().pointer.press_origin() {
+                let start = position(pos);
+
+                self.focused = true;
+                self.selection = Some((start, start));
+            }
+        }
+
+        if response.dragged() {
+            if let (Some(pos), Some((start, _))) =
+                (ui.input().pointer.interact_pos(), self.selection)
+            {
+                self.selection = Some((start, position(pos)));
+            }
+        }
+
+        if self.focused {
+            self.handle_input(ui);
+        }
+
+        self.paint(ui, rect, cell);
+    }
+
+    fn handle_input(self: &mut Self, ui: &egui::Ui) {
+        let events = ui.input().events.clone();
+
+        for event in events {
+            match event {
+                // Pasting also yields text events
+                egui::Event::Text(text) => self.send(Input::Bytes(text.into_bytes())),
+                egui::Event::Key {
+                    key: Key::C,
+                    pressed: true,
+                    modifiers:
+                        Modifiers {
+                            ctrl: true,
+                            shift: true,
+                            ..
+                        },
+                } => self.copy(ui),
+                egui::Event::Key {
+                    key,
+                    pressed: true,
+                    modifiers,
+                } => {
+                    if let Some(b) = key_sequence(key, modifiers) {
+                        self.send(Input::Bytes(b));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn paint(self: &Self, ui: &egui::Ui, rect: Rect, cell: Vec2) {
+        let painter = ui.painter_at(rect);
+        let default_fg = ui.visuals().text_color();
+        let default_bg = ui.visuals().extreme_bg_color;
+        let (cols, rows) = self.screen.size();
+        let cursor = self.screen.cursor();
+
+        painter.rect_filled(rect, 0.0, default_bg);
+
+        for row in 0..rows {
+            for (col, c) in self.screen.line(row).iter().enumerate().take(cols) {
+                let selected = self
+                    .selection
+                    .map(|(start, end)| is_selected((row, col), start, end))
+                    .unwrap_or(false);
+                let inverse = c.inverse ^ selected ^ (cursor == Some((row, col)) && self.focused);
+
+                let (mut fg, mut bg) = (
+                    color(c.fg, c.bold, default_fg),
+                    color(c.bg, false, default_bg),
+                );
+                if inverse {
+                    std::mem::swap(&mut fg, &mut bg);
+                }
+
+                let min = rect.min + Vec2::new(col as f32 * cell.x, row as f32 * cell.y);
+
+                if bg != default_bg {
+                    painter.rect_filled(Rect::from_min_size(min, cell), 0.0, bg);
+                }
+
+                if c.c != ' ' {
+                    painter.text(min, egui::Align2::LEFT_TOP, c.c, TextStyle::Monospace, fg);
+                }
+
+                if c.underline {
+                    painter.line_segment(
+                        [
+                            min + Vec2::new(0.0, cell.y - 1.0),
+                            min + Vec2::new(cell.x, cell.y - 1.0),
+                        ],
+                        (1.0, fg),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn is_selected(pos: (usize, usize), start: (usize, usize), end: (usize, usize)) -> bool {
+    let (start, end) = if start <= end {
+        (start, end)
+    } else {
+        (end, start)
+    };
+
+    start <= pos && pos <= end
+}
+
+// Bold text uses the bright variants of the ANSI colors
+fn color(color: Color, bold: bool, default: Color32) -> Color32 {
+    match color {
+        Color::Default => default,
+        Color::Indexed(i) if i < 8 && bold => ansi_color(i + 8),
+        Color::Indexed(i) => ansi_color(i),
+        Color::Rgb(r, g, b) => Color32::from_rgb(r, g, b),
+    }
+}
+
+fn ansi_color(i: u8) -> Color32 {
+    match i {
+        0..=15 => {
+            let (r, g, b) = ANSI_COLORS[i as usize];
+
+            Color32::from_rgb(r, g, b)
+        }
+        // 6x6x6 color cube
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let i = i - 16;
+
+            Color32::from_rgb(level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        // Grayscale ramp
+        _ => {
+            let v = 8 + (i - 232) * 10;
+
+            Color32::from_rgb(v, v, v)
+        }
+    }
+}
+
+// Translates keys which don't produce text events into what a VT100 would send
+fn key_sequence(key: Key, modifiers: Modifiers) -> Option<Vec<u8>> {
+    if modifiers.ctrl && !modifiers.shift {
+        let letter = match key {
+            Key::A => b'a',
+            Key::B => b'b',
+            Key::C => b'c',
+            Key::D => b'd',
+            Key::E => b'e',
+            Key::F => b'f',
+            Key::G => b'g',
+            Key::H => b'h',
+            Key::I => b'i',
+            Key::J => b'j',
+            Key::K => b'k',
+            Key::L => b'l',
+            Key::M => b'm',
+            Key::N => b'n',
+            Key::O => b'o',
+            Key::P => b'p',
+            Key::Q => b'q',
+            Key::R => b'r',
+            Key::S => b's',
+            Key::T => b't',
+            Key::U => b'u',
+            // Ctrl+V pastes
+            Key::W => b'w',
+            Key::X => b'x',
+            Key::Y => b'y',
+            Key::Z => b'z',
+            _ => 0,
+        };
+
+        if letter != 0 {
+            return Some(vec![letter & 0x1f]);
+        }
+    }
+
+    let sequence: &[u8] = match key {
+        Key::Enter => b"\r",
+        Key::Backspace => b"\x7f",
+        Key::Tab => b"\t",
+        Key::Escape => b"\x1b",
+        Key::ArrowUp => b"\x1b[A",
+        Key::ArrowDown => b"\x1b[B",
+        Key::ArrowRight => b"\x1b[C",
+        Key::ArrowLeft => b"\x1b[D",
+        Key::Home => b"\x1b[H",
+        Key::End => b"\x1b[F",
+        Key::Insert => b"\x1b[2~",
+        Key::Delete => b"\x1b[3~",
+        Key::PageUp => b"\x1b[5~",
+        Key::PageDown => b"\x1b[6~",
+        _ => return None,
+    };
+
+    Some(sequence.to_vec())
+}
@@ -0,0 +1,920 @@
+// Shared by several test crates, none of which uses every helper
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use pojde_rs::{
+    backend::{DockerBackend, Endpoint},
+    instances::Instances,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    sync::oneshot,
+};
+
+#[derive(Clone)]
+pub struct FakeContainer {
+    pub id: String,
+    pub names: Vec<String>,
+    pub state: String,
+    pub status: String,
+    // Pairs of private and public port
+    pub ports: Vec<(u64, Option<u64>)>,
+    pub logs: Vec<(u8, String)>,
+    pub labels: Vec<(String, String)>,
+    // Volumes and bind mounts, i.e. `pojde-ca:/opt/pojde/ca`
+    pub binds: Vec<String>,
+    // Files outside of named volumes, by absolute path
+    pub files: HashMap<String, Vec<u8>>,
+    // The request body of containers created through the API
+    pub config: Value,
+    // Listed, but removed before it can be inspected
+    pub vanished: bool,
+}
+
+impl FakeContainer {
+    pub fn new(id: &str, names: &[&str], state: &str) -> Self {
+        Self {
+            id: id.to_owned(),
+            names: names.iter().map(|n| n.to_string()).collect(),
+            state: state.to_owned(),
+            status: match state {
+                "running" => "Up 2 hours".to_owned(),
+                "exited" => "Exited (0) 2 hours ago".to_owned(),
+                _ => "Created".to_owned(),
+            },
+            ports: vec![],
+            logs: vec![],
+            labels: vec![],
+            binds: vec![],
+            files: HashMap::new(),
+            config: Value::Null,
+            vanished: false,
+        }
+    }
+
+    pub fn with_ports(mut self, ports: &[(u64, Option<u64>)]) -> Self {
+        self.ports = ports.to_vec();
+
+        self
+    }
+
+    pub fn with_status(mut self, status: &str) -> Self {
+        self.status = status.to_owned();
+
+        self
+    }
+
+    pub fn with_labels(mut self, labels: &[(&str, &str)]) -> Self {
+        self.labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        self
+    }
+
+    pub fn with_logs(mut self, logs: &[(u8, &str)]) -> Self {
+        self.logs = logs.iter().map(|(s, l)| (*s, l.to_string())).collect();
+
+        self
+    }
+
+    pub fn vanishing(mut self) -> Self {
+        self.vanished = true;
+
+        self
+    }
+
+    pub fn with_binds(mut self, binds: &[&str]) -> Self {
+        self.binds = binds.iter().map(|b| b.to_string()).collect();
+
+        self
+    }
+
+    // The named volume and the path within it which `path` resolves to
+    fn mount(&self, path: &str) -> Option<(String, String)> {
+        self.binds.iter().find_map(|bind| {
+            let mut parts = bind.splitn(3, ':');
+            let (source, target) = (parts.next()?, parts.next()?);
+
+            if source.starts_with('/') {
+                return None;
+            }
+
+            path.strip_prefix(target)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .map(|rest| (source.to_owned(), rest.to_owned()))
+        })
+    }
+}
+
+#[derive(Default)]
+struct State {
+    containers: Vec<FakeContainer>,
+    // Commands and whether they use a TTY
+    execs: HashMap<String, (Vec<String>, bool)>,
+    // Replayed by the events endpoint, which then closes the stream
+    events: Vec<Value>,
+    // Named volumes and their files, by path within the volume
+    volumes: HashMap<String, HashMap<String, Vec<u8>>>,
+    // `socat TCP-LISTEN` execs which wait for `FakeDocker::connect_in_container`
+    listeners: Vec<oneshot::Sender<DuplexStream>>,
+    requests: Vec<String>,
+}
+
+impl State {
+    fn create_volumes(self: &mut Self, binds: &[String]) {
+        for bind in binds {
+            let source = bind.split(':').next().unwrap_or_default();
+
+            if !source.starts_with('/') {
+                self.volumes.entry(source.to_owned()).or_default();
+            }
+        }
+    }
+
+    fn read_file(self: &Self, c: &FakeContainer, path: &str) -> Option<Vec<u8>> {
+        match c.mount(path) {
+            Some((volume, path)) => self.volumes.get(&volume)?.get(&path).cloned(),
+            None => c.files.get(path).cloned(),
+        }
+    }
+}
+
+// In-process fake of the subset of the Docker Engine API used by `Instances`
+pub struct FakeDocker {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeDocker {
+    pub async fn start(containers: Vec<FakeContainer>) -> Self {
+        let mut state = State::default();
+        for c in &containers {
+            state.create_volumes(&c.binds);
+        }
+        state.containers = containers;
+        let state = Arc::new(Mutex::new(state));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let service_state = state.clone();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(move |_| {
+                let state = service_state.clone();
+
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+                }
+            }));
+
+        tokio::spawn(server);
+
+        Self { addr, state }
+    }
+
+    pub fn instances(&self) -> Instances {
+        Instances::new(Box::new(DockerBackend::new(Endpoint::Tcp(
+            self.addr.to_string(),
+        ))))
+    }
+
+    // Looks up containers by ID or name
+    pub fn container(&self, id: &str) -> FakeContainer {
+        find(&mut self.state.lock().unwrap().containers, id)
+            .unwrap()
+            .clone()
+    }
+
+    pub fn has_container(&self, id: &str) -> bool {
+        find(&mut self.state.lock().unwrap().containers, id).is_some()
+    }
+
+    // Names of all volumes, sorted
+    pub fn volumes(&self) -> Vec<String> {
+        let mut volumes = self
+            .state
+            .lock()
+            .unwrap()
+            .volumes
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        volumes.sort();
+
+        volumes
+    }
+
+    // Reads a file like the daemon would, including files in mounted volumes
+    pub fn file(&self, id: &str, path: &str) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let c = find(&mut state.containers, id)?.clone();
+
+        state.read_file(&c, path)
+    }
+
+    pub fn push_event(&self, kind: &str, action: &str, id: &str, attributes: &[(&str, &str)]) {
+        let attributes = attributes
+            .iter()
+            .map(|(k, v)| (k.to_string(), json!(v)))
+            .collect::<serde_json::Map<_, _>>();
+
+        self.state.lock().unwrap().events.push(json!({
+            "Type": kind,
+            "Action": action,
+            "Actor": { "ID": id, "Attributes": attributes },
+            "time": 1625000000,
+            "timeNano": 1625000000000000000u64
+        }));
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    // Commands of all execs, in the order in which they were created
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        let mut execs = state.execs.iter().collect::<Vec<_>>();
+        execs.sort_by_key(|(id, _)| exec_index(id));
+
+        execs.into_iter().map(|(_, (cmd, _))| cmd.clone()).collect()
+    }
+
+    // Listeners whose sessions are still open
+    pub fn listeners(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .listeners
+            .iter()
+            .filter(|l| !l.is_closed())
+            .count()
+    }
+
+    // Connects to the oldest waiting listener, like a client within the container would
+    pub fn connect_in_container(&self) -> Option<DuplexStream> {
+        let mut state = self.state.lock().unwrap();
+
+        while !state.listeners.is_empty() {
+            let (client, server) = tokio::io::duplex(1024);
+
+            if state.listeners.remove(0).send(server).is_ok() {
+                return Some(client);
+            }
+        }
+
+        None
+    }
+}
+
+fn is_listener(cmd: &[String]) -> bool {
+    cmd.iter().any(|c| c.starts_with("TCP-LISTEN:"))
+}
+
+fn exec_index(id: &str) -> usize {
+    id.trim_start_matches("exec-").parse().unwrap()
+}
+
+fn find<'a>(containers: &'a mut [FakeContainer], id: &str) -> Option<&'a mut FakeContainer> {
+    containers
+        .iter_mut()
+        .find(|c| c.id == id || c.names.iter().any(|n| n.trim_start_matches('/') == id))
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn not_found(id: &str) -> Response<Body> {
+    json_response(
+        StatusCode::NOT_FOUND,
+        json!({ "message": format!("No such container: {}", id) }),
+    )
+}
+
+fn conflict(message: String) -> Response<Body> {
+    json_response(StatusCode::CONFLICT, json!({ "message": message }))
+}
+
+fn no_content() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+pub static LOGS_START: &str = "2021-06-29T21:00:00Z";
+
+pub fn log_timestamp(line: usize) -> String {
+    let start = chrono::DateTime::parse_from_rfc3339(LOGS_START).unwrap();
+
+    (start + chrono::Duration::seconds(line as i64))
+        .with_timezone(&chrono::Utc)
+        .to_rfc3339_opts(chrono::SecondsFormat::Nanos, true)
+}
+
+// Frames in the format of Docker's multiplexed streams
+fn frame(stream: u8, content: &[u8]) -> Vec<u8> {
+    let mut frame = vec![stream, 0, 0, 0];
+    frame.extend_from_slice(&(content.len() as u32).to_be_bytes());
+    frame.extend_from_slice(content);
+
+    frame
+}
+
+fn multiplexed(frames: &[(u8, String)]) -> Response<Body> {
+    let body = frames
+        .iter()
+        .flat_map(|(stream, content)| frame(*stream, content.as_bytes()))
+        .collect::<Vec<_>>();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/vnd.docker.raw-stream")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// Echoes the command and then everything written to stdin, like `cat` would
+fn hijacked(upgrade: hyper::upgrade::OnUpgrade, cmd: Vec<String>, tty: bool) -> Response<Body> {
+    tokio::spawn(async move {
+        let mut conn = upgrade.await.unwrap();
+        let encode = |content: &[u8]| {
+            if tty {
+                content.to_vec()
+            } else {
+                frame(1, content)
+            }
+        };
+
+        conn.write_all(&encode((cmd.join(" ") + "\n").as_bytes()))
+            .await
+            .unwrap();
+
+        let mut buf = vec![0; 1024];
+        loop {
+            match conn.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => conn.write_all(&encode(&buf[..n])).await.unwrap(),
+            }
+        }
+
+        conn.shutdown().await.ok();
+    });
+
+    switching_protocols()
+}
+
+// Behaves like `sh -c 'echo $$ && exec socat -d -d TCP-LISTEN:... STDIO'`, using the exec's index as its PID
+fn listening(
+    upgrade: hyper::upgrade::OnUpgrade,
+    pid: usize,
+    accepted: oneshot::Receiver<DuplexStream>,
+) -> Response<Body> {
+    tokio::spawn(async move {
+        let conn = upgrade.await.unwrap();
+        let (mut stdin, mut stdout) = tokio::io::split(conn);
+
+        stdout
+            .write_all(&frame(1, format!("{}\n", pid).as_bytes()))
+            .await
+            .unwrap();
+
+        // The session may be closed before a connection arrives
+        let mut buf = vec![0; 1024];
+        let stream = tokio::select! {
+            stream = accepted => match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            },
+            _ = stdin.read(&mut buf) => return,
+        };
+
+        stdout
+            .write_all(&frame(
+                2,
+                b"socat[1] N accepting connection from AF=2 127.0.0.1:40000\n",
+            ))
+            .await
+            .unwrap();
+
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let mut input = vec![0; 1024];
+        loop {
+            tokio::select! {
+                n = reader.read(&mut buf) => match n {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => stdout.write_all(&frame(1, &buf[..n])).await.unwrap(),
+                },
+                n = stdin.read(&mut input) => match n {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => writer.write_all(&input[..n]).await.unwrap(),
+                },
+            }
+        }
+
+        stdout.shutdown().await.ok();
+    });
+
+    switching_protocols()
+}
+
+fn switching_protocols() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "tcp")
+        .body(Body::empty())
+        .unwrap()
+}
+
+// Wraps a single file like `GET /containers/{id}/archive` does
+fn archive(path: &str, content: &[u8]) -> Response<Body> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+
+    let mut builder = tar::Builder::new(vec![]);
+    builder
+        .append_data(
+            &mut header,
+            path.rsplit('/').next().unwrap_or(path),
+            content,
+        )
+        .unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/x-tar")
+        .body(Body::from(builder.into_inner().unwrap()))
+        .unwrap()
+}
+
+fn summary_json(c: &FakeContainer) -> Value {
+    json!({
+        "Id": c.id,
+        "Names": c.names,
+        "Image": "pojntfx/pojde:latest",
+        "ImageID": "sha256:0000000000000000000000000000000000000000000000000000000000000000",
+        "Command": "/lib/systemd/systemd",
+        "Created": 1625000000,
+        "Ports": c.ports.iter().map(|(private_port, public_port)| match public_port {
+            Some(public_port) => json!({
+                "IP": "0.0.0.0",
+                "PrivatePort": private_port,
+                "PublicPort": public_port,
+                "Type": "tcp"
+            }),
+            None => json!({ "PrivatePort": private_port, "Type": "tcp" }),
+        }).collect::<Vec<_>>(),
+        "Labels": c.labels.iter().cloned().collect::<HashMap<_, _>>(),
+        "State": c.state,
+        "Status": c.status,
+        "HostConfig": { "NetworkMode": "default" },
+        "NetworkSettings": { "Networks": {} },
+        "Mounts": []
+    })
+}
+
+fn details_json(c: &FakeContainer) -> Value {
+    json!({
+        "AppArmorProfile": "",
+        "Args": [],
+        "Config": {
+            "AttachStderr": false,
+            "AttachStdin": false,
+            "AttachStdout": false,
+            "Cmd": ["/lib/systemd/systemd"],
+            "Domainname": "",
+            "Entrypoint": null,
+            "Env": ["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"],
+            "ExposedPorts": {},
+            "Hostname": c.id,
+            "Image": "pojntfx/pojde:latest",
+            "Labels": c.labels.iter().cloned().collect::<HashMap<_, _>>(),
+            "OnBuild": null,
+            "OpenStdin": false,
+            "StdinOnce": false,
+            "Tty": false,
+            "User": "",
+            "WorkingDir": ""
+        },
+        "Created": "2021-06-29T20:53:20.000000000Z",
+        "Driver": "overlay2",
+        "HostConfig": {
+            "CgroupParent": "",
+            "ContainerIDFile": "",
+            "CpuShares": 0,
+            "CpusetCpus": "",
+            "Memory": 0,
+            "MemorySwap": 0,
+            "NetworkMode": "default",
+            "PidMode": "",
+            "PortBindings": {},
+            "Privileged": false,
+            "PublishAllPorts": false,
+            "ReadonlyRootfs": false
+        },
+        "HostnamePath": "",
+        "HostsPath": "",
+        "LogPath": "",
+        "Id": c.id,
+        "Image": "sha256:0000000000000000000000000000000000000000000000000000000000000000",
+        "MountLabel": "",
+        "Name": c.names.first().cloned().unwrap_or_default(),
+        "NetworkSettings": {
+            "Bridge": "",
+            "Gateway": "172.17.0.1",
+            "IPAddress": "172.17.0.2",
+            "IPPrefixLen": 16,
+            "MacAddress": "02:42:ac:11:00:02",
+            "Ports": {},
+            "Networks": {}
+        },
+        "Path": "/lib/systemd/systemd",
+        "ProcessLabel": "",
+        "ResolvConfPath": "",
+        "RestartCount": 0,
+        "State": {
+            "Error": "",
+            "ExitCode": 0,
+            "FinishedAt": "0001-01-01T00:00:00Z",
+            "OOMKilled": false,
+            "Dead": c.state == "dead",
+            "Paused": c.state == "paused",
+            "Pid": 0,
+            "Restarting": c.state == "restarting",
+            "Running": c.state == "running",
+            "StartedAt": "2021-06-29T20:53:21.000000000Z",
+            "Status": c.state
+        },
+        "Mounts": []
+    })
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let upgrade = hyper::upgrade::on(&mut req);
+    let wants_upgrade = req.headers().contains_key("Upgrade");
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let query = req.uri().query().unwrap_or("").to_owned();
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+
+    let mut guard = state.lock().unwrap();
+    let state = &mut *guard;
+    state.requests.push(format!("{} {}", method, path));
+
+    // Strip the optional API version prefix, i.e. `/v1.41`
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty() && !s.starts_with("v1."))
+        .collect::<Vec<_>>();
+
+    let res = match (&method, segments.as_slice()) {
+        (&Method::GET, ["containers", "json"]) => {
+            let filters = url::form_urlencoded::parse(query.as_bytes())
+                .find(|(k, _)| k == "filters")
+                .map(|(_, v)| serde_json::from_str::<HashMap<String, Vec<String>>>(&v).unwrap())
+                .unwrap_or_default();
+
+            let names = filters.get("name").cloned().unwrap_or_default();
+
+            json_response(
+                StatusCode::OK,
+                Value::Array(
+                    state
+                        .containers
+                        .iter()
+                        .filter(|c| {
+                            names
+                                .iter()
+                                .all(|f| c.names.iter().any(|n| n.contains(f.as_str())))
+                        })
+                        .map(summary_json)
+                        .collect(),
+                ),
+            )
+        }
+        (&Method::POST, ["containers", "create"]) => {
+            let name = url::form_urlencoded::parse(query.as_bytes())
+                .find(|(k, _)| k == "name")
+                .map(|(_, v)| v.into_owned())
+                .unwrap();
+
+            match find(&mut state.containers, &name) {
+                Some(c) => conflict(format!(
+                    "Conflict. The container name \"/{}\" is already in use by container \"{}\"",
+                    name, c.id
+                )),
+                None => {
+                    let config = serde_json::from_slice::<Value>(&body).unwrap();
+                    let host_config = &config["HostConfig"];
+
+                    let mut c = FakeContainer::new(
+                        &format!("created-{}", state.containers.len()),
+                        &[&format!("/{}", name)],
+                        "created",
+                    );
+                    c.ports = host_config["PortBindings"]
+                        .as_object()
+                        .into_iter()
+                        .flatten()
+                        .map(|(port, bindings)| {
+                            let private_port = port.split('/').next().unwrap().parse().unwrap();
+                            let public_port = bindings[0]["HostPort"]
+                                .as_str()
+                                .and_then(|p| p.parse().ok());
+
+                            (private_port, public_port)
+                        })
+                        .collect();
+                    c.labels = config["Labels"]
+                        .as_object()
+                        .into_iter()
+                        .flatten()
+                        .map(|(k, v)| (k.to_owned(), v.as_str().unwrap().to_owned()))
+                        .collect();
+                    c.binds = host_config["Binds"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|b| b.as_str().unwrap().to_owned())
+                        .collect();
+                    c.config = config;
+
+                    let id = c.id.clone();
+                    state.create_volumes(&c.binds);
+                    state.containers.push(c);
+
+                    json_response(StatusCode::CREATED, json!({ "Id": id, "Warnings": [] }))
+                }
+            }
+        }
+        // Containers are always removed with `force`, so running ones are fine too
+        (&Method::DELETE, ["containers", id]) => {
+            match state.containers.iter().position(|c| {
+                c.id == *id || c.names.iter().any(|n| n.trim_start_matches('/') == *id)
+            }) {
+                Some(i) => {
+                    state.containers.remove(i);
+
+                    no_content()
+                }
+                None => not_found(id),
+            }
+        }
+        (&Method::GET, ["containers", id, "archive"]) => match find(&mut state.containers, id) {
+            Some(c) => {
+                let c = c.clone();
+                let path = url::form_urlencoded::parse(query.as_bytes())
+                    .find(|(k, _)| k == "path")
+                    .map(|(_, v)| v.into_owned())
+                    .unwrap();
+
+                match state.read_file(&c, &path) {
+                    Some(content) => archive(&path, &content),
+                    None => json_response(
+                        StatusCode::NOT_FOUND,
+                        json!({ "message": format!("Could not find the file {} in container {}", path, id) }),
+                    ),
+                }
+            }
+            None => not_found(id),
+        },
+        // Extracts the uploaded tarball into the directory given by `path`
+        (&Method::PUT, ["containers", id, "archive"]) => match find(&mut state.containers, id) {
+            Some(c) => {
+                let c = c.clone();
+                let dir = url::form_urlencoded::parse(query.as_bytes())
+                    .find(|(k, _)| k == "path")
+                    .map(|(_, v)| v.into_owned())
+                    .unwrap();
+
+                let mut archive = tar::Archive::new(&body[..]);
+                let files = archive
+                    .entries()
+                    .unwrap()
+                    .map(|entry| {
+                        let mut entry = entry.unwrap();
+                        let path = format!(
+                            "{}/{}",
+                            dir.trim_end_matches('/'),
+                            entry.path().unwrap().display()
+                        );
+
+                        let mut content = vec![];
+                        std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+
+                        (path, content)
+                    })
+                    .collect::<Vec<_>>();
+
+                for (path, content) in files {
+                    match c.mount(&path) {
+                        Some((volume, path)) => {
+                            state
+                                .volumes
+                                .entry(volume)
+                                .or_default()
+                                .insert(path, content);
+                        }
+                        None => {
+                            find(&mut state.containers, id)
+                                .unwrap()
+                                .files
+                                .insert(path, content);
+                        }
+                    }
+                }
+
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .body(Body::empty())
+                    .unwrap()
+            }
+            None => not_found(id),
+        },
+        // Like Docker, volumes which are mounted by a container can't be removed
+        (&Method::DELETE, ["volumes", name]) => {
+            if !state.volumes.contains_key(*name) {
+                json_response(
+                    StatusCode::NOT_FOUND,
+                    json!({ "message": format!("get {}: no such volume", name) }),
+                )
+            } else if let Some(c) = state
+                .containers
+                .iter()
+                .find(|c| c.binds.iter().any(|b| b.split(':').next() == Some(*name)))
+            {
+                conflict(format!("remove {}: volume is in use - [{}]", name, c.id))
+            } else {
+                state.volumes.remove(*name);
+
+                no_content()
+            }
+        }
+        (&Method::GET, ["containers", id, "json"]) => match find(&mut state.containers, id) {
+            Some(c) if !c.vanished => json_response(StatusCode::OK, details_json(c)),
+            _ => not_found(id),
+        },
+        (&Method::POST, ["containers", id, action @ ("start" | "stop" | "restart")]) => {
+            match find(&mut state.containers, id) {
+                Some(c) => {
+                    match *action {
+                        "stop" => {
+                            c.state = "exited".to_owned();
+                            c.status = "Exited (0) 1 second ago".to_owned();
+                        }
+                        _ => {
+                            c.state = "running".to_owned();
+                            c.status = "Up 1 second".to_owned();
+                        }
+                    };
+
+                    no_content()
+                }
+                None => not_found(id),
+            }
+        }
+        (&Method::GET, ["containers", id, "logs"]) => match find(&mut state.containers, id) {
+            Some(c) => {
+                let params =
+                    url::form_urlencoded::parse(query.as_bytes()).collect::<HashMap<_, _>>();
+                let timestamps = params.get("timestamps").map_or(false, |t| t == "true");
+                let tail = params
+                    .get("tail")
+                    .and_then(|t| t.parse::<usize>().ok())
+                    .unwrap_or(c.logs.len());
+
+                // Lines are one second apart, starting at `LOGS_START`
+                let logs = c
+                    .logs
+                    .iter()
+                    .enumerate()
+                    .skip(c.logs.len().saturating_sub(tail))
+                    .map(|(i, (stream, line))| {
+                        if timestamps {
+                            (*stream, format!("{} {}", log_timestamp(i), line))
+                        } else {
+                            (*stream, line.to_owned())
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                multiplexed(&logs)
+            }
+            None => not_found(id),
+        },
+        (&Method::POST, ["containers", id, "exec"]) => match find(&mut state.containers, id) {
+            Some(_) => {
+                let options = serde_json::from_slice::<Value>(&body).unwrap();
+                let cmd = options["Cmd"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|c| c.as_str().unwrap().to_owned())
+                    .collect::<Vec<_>>();
+
+                let tty = options["Tty"].as_bool().unwrap_or(false);
+
+                let exec_id = format!("exec-{}", state.execs.len());
+                state.execs.insert(exec_id.clone(), (cmd, tty));
+
+                json_response(StatusCode::CREATED, json!({ "Id": exec_id }))
+            }
+            None => not_found(id),
+        },
+        // Echo the command so that tests can check what has been run; listeners wait for a connection instead
+        (&Method::POST, ["exec", id, "start"]) => match state.execs.get(*id).cloned() {
+            Some((cmd, _)) if wants_upgrade && is_listener(&cmd) => {
+                let (sender, receiver) = oneshot::channel();
+                state.listeners.push(sender);
+
+                listening(upgrade, exec_index(id), receiver)
+            }
+            Some((cmd, tty)) if wants_upgrade => hijacked(upgrade, cmd, tty),
+            Some((cmd, _)) => multiplexed(&[(1, cmd.join(" ") + "\n")]),
+            None => json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "message": format!("No such exec instance: {}", id) }),
+            ),
+        },
+        // No images exist, so they are always pulled
+        (&Method::GET, ["images", .., "json"]) => {
+            json_response(StatusCode::NOT_FOUND, json!({ "message": "no such image" }))
+        }
+        (&Method::POST, ["images", "create"]) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                [
+                    json!({ "status": "Pulling from pojntfx/pojde", "id": "latest" }),
+                    json!({
+                        "status": "Downloading",
+                        "id": "a1b2",
+                        "progressDetail": { "current": 512, "total": 1024 }
+                    }),
+                    json!({ "status": "Pull complete", "id": "a1b2" }),
+                ]
+                .iter()
+                .map(|p| p.to_string() + "\n")
+                .collect::<String>(),
+            ))
+            .unwrap(),
+        (&Method::GET, ["events"]) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                state
+                    .events
+                    .iter()
+                    .map(|e| e.to_string() + "\n")
+                    .collect::<String>(),
+            ))
+            .unwrap(),
+        (&Method::POST, ["exec", id, "resize"]) => match state.execs.get(*id) {
+            Some(_) => no_content(),
+            None => json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "message": format!("No such exec instance: {}", id) }),
+            ),
+        },
+        (&Method::GET, ["exec", id, "json"]) => match state.execs.get(*id) {
+            Some(_) => json_response(
+                StatusCode::OK,
+                json!({ "ID": id, "Running": false, "ExitCode": 0 }),
+            ),
+            None => json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "message": format!("No such exec instance: {}", id) }),
+            ),
+        },
+        _ => json_response(
+            StatusCode::NOT_FOUND,
+            json!({ "message": format!("page not found: {} {}", method, path) }),
+        ),
+    };
+
+    Ok(res)
+}
@@ -0,0 +1,274 @@
+mod common;
+
+use std::{
+    net::{Ipv4Addr, TcpListener},
+    sync::Mutex,
+};
+
+use common::{FakeContainer, FakeDocker};
+use pojde_rs::{
+    forward::{forward, Direction, ForwardSpec},
+    instances::RemoveScope,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot,
+    time::{sleep, Duration},
+};
+
+fn free_port() -> u16 {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[test]
+fn parses_forward_specs() {
+    assert_eq!(
+        "127.0.0.1:5000:localhost:8080".parse::<ForwardSpec>(),
+        Ok(ForwardSpec {
+            local_host: "127.0.0.1".to_owned(),
+            local_port: 5000,
+            remote_host: "localhost".to_owned(),
+            remote_port: 8080,
+        })
+    );
+
+    assert_eq!(
+        "5000:8080".parse::<ForwardSpec>().unwrap().to_string(),
+        "localhost:5000:localhost:8080"
+    );
+
+    assert!("localhost:5000".parse::<ForwardSpec>().is_err());
+    assert!("localhost:http:localhost:80"
+        .parse::<ForwardSpec>()
+        .is_err());
+}
+
+#[tokio::test]
+async fn forwards_local_connections_into_instance() {
+    let docker =
+        FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "running")]).await;
+    let instances = docker.instances();
+
+    let port = free_port();
+    let specs = vec![format!("127.0.0.1:{}:localhost:5000", port)
+        .parse()
+        .unwrap()];
+    let (stop, stopped) = oneshot::channel::<()>();
+
+    let client = async move {
+        let mut stream = loop {
+            match TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await {
+                Ok(stream) => break stream,
+                Err(_) => sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        // The fake daemon echoes the command before echoing stdin
+        let mut greeting = vec![0; "socat - TCP:localhost:5000\n".len()];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, b"socat - TCP:localhost:5000\n");
+
+        stream.write_all(b"ping").await.unwrap();
+
+        let mut echo = vec![0; 4];
+        stream.read_exact(&mut echo).await.unwrap();
+        assert_eq!(echo, b"ping");
+
+        stop.send(()).unwrap();
+    };
+
+    let (res, _) = tokio::join!(
+        forward(
+            &instances,
+            "test",
+            &specs,
+            Direction::Local,
+            async {
+                stopped.await.ok();
+            },
+            &|spec, e| panic!("could not forward {}: {}", spec, e)
+        ),
+        client
+    );
+
+    res.unwrap();
+}
+
+#[tokio::test]
+async fn forward_reports_missing_instances_on_start() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    let specs = vec![format!("127.0.0.1:{}:localhost:5000", free_port())
+        .parse()
+        .unwrap()];
+
+    let res = forward(
+        &instances,
+        "missing",
+        &specs,
+        Direction::Local,
+        futures::future::pending(),
+        &|_, _| {},
+    )
+    .await;
+
+    assert!(matches!(res, Err(pojde_rs::Error::NotFound { .. })));
+}
+
+#[tokio::test]
+async fn forward_survives_failed_connections() {
+    let docker =
+        FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "running")]).await;
+    let instances = docker.instances();
+
+    let port = free_port();
+    let specs = vec![format!("127.0.0.1:{}:localhost:5000", port)
+        .parse()
+        .unwrap()];
+    let errors = Mutex::new(vec![]);
+    let (stop, stopped) = oneshot::channel::<()>();
+
+    let client = async {
+        let mut stream = loop {
+            match TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await {
+                Ok(stream) => break stream,
+                Err(_) => sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        // Wait for the first connection to reach the instance
+        let mut greeting = vec![0; "socat - TCP:localhost:5000\n".len()];
+        stream.read_exact(&mut greeting).await.unwrap();
+        drop(stream);
+
+        // Further connections fail once the container is gone
+        docker
+            .instances()
+            .remove(&["test".to_owned()], &RemoveScope::default())
+            .await
+            .unwrap();
+
+        for attempt in 1..=2 {
+            let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+                .await
+                .unwrap();
+
+            // The forward drops the connection but keeps listening
+            assert_eq!(stream.read(&mut [0; 1]).await.unwrap(), 0);
+
+            while errors.lock().unwrap().len() < attempt {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        stop.send(()).unwrap();
+    };
+
+    let (res, _) = tokio::join!(
+        forward(
+            &instances,
+            "test",
+            &specs,
+            Direction::Local,
+            async {
+                stopped.await.ok();
+            },
+            &|spec, e| errors.lock().unwrap().push(format!("{}: {}", spec, e))
+        ),
+        client
+    );
+
+    res.unwrap();
+    assert!(errors.lock().unwrap()[0].contains("does not exist"));
+}
+
+#[tokio::test]
+async fn forwards_parallel_remote_connections_to_host() {
+    let docker =
+        FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "running")]).await;
+    let instances = docker.instances();
+
+    // Echoes connections on the host
+    let host = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let port = host.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = host.accept().await.unwrap();
+
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+
+                tokio::io::copy(&mut reader, &mut writer).await.ok();
+            });
+        }
+    });
+
+    let specs = vec![format!("127.0.0.1:{}:localhost:5000", port)
+        .parse()
+        .unwrap()];
+    let (stop, stopped) = oneshot::channel::<()>();
+
+    let client = async {
+        while docker.listeners() < 6 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // All connections are accepted at once, without waiting for listeners to be replaced
+        let mut streams = (0..6)
+            .map(|_| docker.connect_in_container().unwrap())
+            .collect::<Vec<_>>();
+
+        for (i, stream) in streams.iter_mut().enumerate() {
+            let message = format!("ping {}", i);
+            stream.write_all(message.as_bytes()).await.unwrap();
+
+            let mut echo = vec![0; message.len()];
+            stream.read_exact(&mut echo).await.unwrap();
+            assert_eq!(echo, message.as_bytes());
+        }
+
+        // Accepted listeners are replaced
+        while docker.listeners() < 6 {
+            sleep(Duration::from_millis(10)).await;
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        stop.send(()).unwrap();
+    };
+
+    let (res, _) = tokio::join!(
+        forward(
+            &instances,
+            "test",
+            &specs,
+            Direction::Remote,
+            async {
+                stopped.await.ok();
+            },
+            &|spec, e| panic!("could not forward {}: {}", spec, e)
+        ),
+        client
+    );
+
+    res.unwrap();
+
+    // Only the waiting listeners of this forward are stopped, by their PIDs
+    let commands = docker.commands();
+    let kills = commands
+        .iter()
+        .filter(|c| c[0] == "kill" || c[0] == "pkill")
+        .collect::<Vec<_>>();
+    assert_eq!(kills.len(), 1);
+
+    let mut pids = kills[0][1..].to_vec();
+    pids.sort_by_key(|pid| pid.parse::<usize>().unwrap());
+    assert_eq!(pids, (6..12).map(|i| i.to_string()).collect::<Vec<_>>());
+}
@@ -0,0 +1,856 @@
+mod common;
+
+use common::{log_timestamp, FakeContainer, FakeDocker};
+use futures::StreamExt;
+use pojde_rs::{
+    backend::{Chunk, LogQuery, PullProgress},
+    instances::{
+        validate_name, ApplyOptions, InstanceEventKind, InstanceHealth, InstanceStatus, Label,
+        LifecycleAction, RemoveScope, Resources, Selector,
+    },
+    plan::{Operation, PlannedChange, Resource},
+    Error,
+};
+use tokio::io::AsyncWriteExt;
+
+#[tokio::test]
+async fn get_instances_lists_pojde_containers() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-test"], "running")
+            .with_ports(&[(8001, Some(8001)), (8000, Some(8000)), (22, Some(8006))])
+            .with_status("Up 2 hours (healthy)"),
+        FakeContainer::new("2", &["/pojde-stopped"], "exited"),
+    ])
+    .await;
+
+    let instances = docker.instances().get_instances().await.unwrap();
+
+    assert_eq!(instances.len(), 2);
+
+    assert_eq!(instances[0].name, "test");
+    assert_eq!(instances[0].start_port, Some(8000));
+    assert_eq!(instances[0].end_port, Some(8006));
+    assert_eq!(instances[0].status, InstanceStatus::Running);
+    assert_eq!(instances[0].health, Some(InstanceHealth::Healthy));
+    assert!(instances[0].uptime.is_some());
+
+    assert_eq!(instances[1].name, "stopped");
+    assert_eq!(instances[1].status, InstanceStatus::Exited(0));
+    assert_eq!(instances[1].health, None);
+    assert!(instances[1].uptime.is_none());
+}
+
+#[tokio::test]
+async fn get_instances_handles_unpublished_ports() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-unpublished"], "running")
+            .with_ports(&[(8000, None), (8001, None)]),
+        FakeContainer::new("2", &["/pojde-partial"], "running")
+            .with_ports(&[(8000, None), (8001, Some(9001))]),
+    ])
+    .await;
+
+    let instances = docker.instances().get_instances().await.unwrap();
+
+    assert_eq!(instances[0].start_port, None);
+    assert_eq!(instances[0].end_port, None);
+
+    assert_eq!(instances[1].start_port, Some(9001));
+    assert_eq!(instances[1].end_port, Some(9001));
+}
+
+#[tokio::test]
+async fn get_instances_skips_substring_matches() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-test"], "running"),
+        FakeContainer::new("2", &["/my-pojde-test"], "running"),
+        FakeContainer::new("3", &["/other"], "running"),
+    ])
+    .await;
+
+    let instances = docker.instances().get_instances().await.unwrap();
+
+    assert_eq!(instances.len(), 1);
+    assert_eq!(instances[0].name, "test");
+}
+
+#[tokio::test]
+async fn get_instances_picks_matching_name() {
+    let docker = FakeDocker::start(vec![FakeContainer::new(
+        "1",
+        &["/other/alias", "/pojde-test"],
+        "running",
+    )])
+    .await;
+
+    let instances = docker.instances().get_instances().await.unwrap();
+
+    assert_eq!(instances.len(), 1);
+    assert_eq!(instances[0].name, "test");
+}
+
+#[tokio::test]
+async fn get_instances_skips_containers_removed_while_listing() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-test"], "running"),
+        FakeContainer::new("2", &["/pojde-gone"], "running").vanishing(),
+    ])
+    .await;
+
+    let instances = docker.instances().get_instances().await.unwrap();
+
+    assert_eq!(instances.len(), 1);
+    assert_eq!(instances[0].name, "test");
+}
+
+#[tokio::test]
+async fn lifecycle_changes_state() {
+    let docker = FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "exited")]).await;
+    let instances = docker.instances();
+
+    instances.start("test").await.unwrap();
+    assert_eq!(docker.container("1").state, "running");
+
+    instances.stop("test").await.unwrap();
+    assert_eq!(docker.container("1").state, "exited");
+
+    instances.restart("test").await.unwrap();
+    assert_eq!(docker.container("1").state, "running");
+
+    assert!(docker
+        .requests()
+        .iter()
+        .any(|r| r.starts_with("POST") && r.ends_with("/containers/pojde-test/restart")));
+}
+
+#[tokio::test]
+async fn lifecycle_reports_missing_instances() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    for res in [
+        instances.start("missing").await,
+        instances.stop("missing").await,
+        instances.restart("missing").await,
+    ] {
+        match res {
+            Err(Error::NotFound { name, .. }) => assert_eq!(name, "missing"),
+            _ => panic!("expected a not found error"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn run_all_reports_every_instance() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-first"], "exited"),
+        FakeContainer::new("2", &["/pojde-second"], "exited"),
+    ])
+    .await;
+    let instances = docker.instances();
+
+    let names = vec![
+        "first".to_owned(),
+        "missing".to_owned(),
+        "second".to_owned(),
+    ];
+    let results = instances.run_all(&names, LifecycleAction::Start).await;
+
+    assert_eq!(
+        results.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(),
+        names
+    );
+    assert!(results[0].1.is_ok());
+    assert!(matches!(&results[1].1, Err(Error::NotFound { name, .. }) if name == "missing"));
+    assert!(results[2].1.is_ok());
+
+    // The failure in the middle must not keep the last instance from starting
+    assert_eq!(docker.container("2").state, "running");
+}
+
+#[tokio::test]
+async fn get_logs_separates_streams() {
+    let docker = FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "running")
+        .with_logs(&[(1, "out\n"), (2, "err\n")])])
+    .await;
+    let instances = docker.instances();
+
+    let logs = instances
+        .get_logs("test", &LogQuery::default())
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(logs.len(), 2);
+    assert!(matches!(&logs[0], Ok(Chunk::StdOut(b)) if b == b"out\n"));
+    assert!(matches!(&logs[1], Ok(Chunk::StdErr(b)) if b == b"err\n"));
+}
+
+#[tokio::test]
+async fn get_logs_applies_query() {
+    let docker = FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "running")
+        .with_logs(&[
+            (1, "first\n"),
+            (1, "second\n"),
+            (2, "third\n"),
+            (1, "fourth\n"),
+        ])])
+    .await;
+    let instances = docker.instances();
+
+    let lines = |logs: Vec<Result<Chunk, Error>>| {
+        logs.into_iter()
+            .map(|l| match l.unwrap() {
+                Chunk::StdOut(b) | Chunk::StdErr(b) => String::from_utf8(b).unwrap(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let tail = LogQuery {
+        tail: Some(2),
+        ..Default::default()
+    };
+    let logs = instances
+        .get_logs("test", &tail)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(lines(logs), vec!["third\n", "fourth\n"]);
+
+    // `until` is inclusive and must not leak the timestamps it relies on
+    let until = LogQuery {
+        until: Some(log_timestamp(1).parse().unwrap()),
+        ..Default::default()
+    };
+    let logs = instances
+        .get_logs("test", &until)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(lines(logs), vec!["first\n", "second\n"]);
+
+    let timestamps = LogQuery {
+        tail: Some(1),
+        timestamps: true,
+        ..Default::default()
+    };
+    let logs = instances
+        .get_logs("test", &timestamps)
+        .await
+        .collect()
+        .await;
+    assert_eq!(lines(logs), vec![format!("{} fourth\n", log_timestamp(3))]);
+}
+
+#[tokio::test]
+async fn enter_streams_stdin() {
+    let docker =
+        FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "running")]).await;
+    let instances = docker.instances();
+
+    let mut session = instances
+        .enter("test", Some(vec!["cat".to_owned()]))
+        .await
+        .unwrap();
+
+    assert!(matches!(session.output.next().await, Some(Ok(Chunk::StdOut(b))) if b == b"cat\n"));
+
+    session.input.write_all(b"ping").await.unwrap();
+    assert!(matches!(session.output.next().await, Some(Ok(Chunk::StdOut(b))) if b == b"ping"));
+
+    instances.resize(&session.id, 120, 40).await.unwrap();
+
+    session.input.shutdown().await.unwrap();
+    assert!(session.output.next().await.is_none());
+}
+
+#[tokio::test]
+async fn exec_separates_streams_without_tty() {
+    let docker =
+        FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "running")]).await;
+    let instances = docker.instances();
+
+    let mut session = instances
+        .exec("test", vec!["echo".to_owned(), "hi".to_owned()], false)
+        .await
+        .unwrap();
+
+    assert!(matches!(session.output.next().await, Some(Ok(Chunk::StdOut(b))) if b == b"echo hi\n"));
+}
+
+#[tokio::test]
+async fn enter_reports_missing_instances() {
+    let docker = FakeDocker::start(vec![]).await;
+
+    match docker.instances().enter("missing", None).await {
+        Err(Error::NotFound { name, .. }) => assert_eq!(name, "missing"),
+        _ => panic!("expected a not found error"),
+    }
+}
+
+#[tokio::test]
+async fn resolve_matches_globs_and_labels() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-team-a"], "running").with_labels(&[("project", "x")]),
+        FakeContainer::new("2", &["/pojde-team-b"], "running").with_labels(&[("project", "y")]),
+        FakeContainer::new("3", &["/pojde-solo"], "running").with_labels(&[("project", "x")]),
+    ])
+    .await;
+    let instances = docker.instances();
+
+    let resolve = |selector: Selector| {
+        let instances = &instances;
+
+        async move { instances.resolve(&selector).await.unwrap() }
+    };
+
+    assert_eq!(
+        resolve(Selector::names(&["team-*".to_owned()])).await,
+        vec!["team-a", "team-b"]
+    );
+
+    // Literal names are kept so that operations can report missing instances
+    assert_eq!(
+        resolve(Selector::names(&["missing".to_owned(), "solo".to_owned()])).await,
+        vec!["missing", "solo"]
+    );
+
+    let project = Label {
+        key: "project".to_owned(),
+        value: "x".to_owned(),
+    };
+
+    assert_eq!(
+        resolve(Selector {
+            labels: vec![project.clone()],
+            ..Default::default()
+        })
+        .await,
+        vec!["team-a", "solo"]
+    );
+
+    assert_eq!(
+        resolve(Selector {
+            patterns: vec!["team-*".to_owned()],
+            labels: vec![project],
+            ..Default::default()
+        })
+        .await,
+        vec!["team-a"]
+    );
+
+    assert_eq!(
+        resolve(Selector {
+            all: true,
+            patterns: vec!["solo".to_owned()],
+            ..Default::default()
+        })
+        .await,
+        vec!["team-a", "team-b", "solo"]
+    );
+}
+
+#[test]
+fn labels_parse_from_key_value_pairs() {
+    assert_eq!(
+        "project=x=y".parse::<Label>(),
+        Ok(Label {
+            key: "project".to_owned(),
+            value: "x=y".to_owned(),
+        })
+    );
+    assert!("project".parse::<Label>().is_err());
+    assert!("=x".parse::<Label>().is_err());
+}
+
+#[tokio::test]
+async fn watch_yields_instance_events() {
+    let docker = FakeDocker::start(vec![]).await;
+    docker.push_event("container", "create", "1", &[("name", "pojde-test")]);
+    docker.push_event("container", "start", "1", &[("name", "pojde-test")]);
+    docker.push_event(
+        "container",
+        "health_status: healthy",
+        "1",
+        &[("name", "pojde-test")],
+    );
+    docker.push_event(
+        "container",
+        "die",
+        "1",
+        &[("name", "pojde-test"), ("exitCode", "137")],
+    );
+    // Neither other containers nor the shared CA volume belong to an instance
+    docker.push_event("container", "start", "2", &[("name", "postgres")]);
+    docker.push_event("volume", "create", "pojde-ca", &[]);
+    docker.push_event("image", "pull", "pojntfx/pojde:latest", &[]);
+    docker.push_event("volume", "destroy", "pojde-my-test-home-user", &[]);
+    docker.push_event("container", "destroy", "1", &[("name", "pojde-test")]);
+
+    let instances = docker.instances();
+    let events = instances
+        .watch()
+        .map(|e| {
+            let e = e.unwrap();
+
+            (e.name, e.kind)
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(
+        events,
+        vec![
+            ("test".to_owned(), InstanceEventKind::Created),
+            ("test".to_owned(), InstanceEventKind::Started),
+            (
+                "test".to_owned(),
+                InstanceEventKind::HealthChanged(InstanceHealth::Healthy)
+            ),
+            ("test".to_owned(), InstanceEventKind::Died(Some(137))),
+            (
+                "my-test".to_owned(),
+                InstanceEventKind::VolumeRemoved("pojde-my-test-home-user".to_owned())
+            ),
+            ("test".to_owned(), InstanceEventKind::Removed),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pull_image_reports_progress() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    let progress = instances
+        .pull_image("test", false)
+        .map(|p| p.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(progress.len(), 3);
+    assert_eq!(
+        progress[1],
+        PullProgress {
+            id: Some("a1b2".to_owned()),
+            status: "Downloading".to_owned(),
+            current: Some(512),
+            total: Some(1024),
+        }
+    );
+    assert_eq!(progress[2].current, None);
+}
+
+#[test]
+fn names_follow_docker_rules() {
+    assert!(validate_name("team-a_1.0").is_ok());
+    assert!(validate_name("").is_err());
+    assert!(validate_name("-a").is_err());
+    assert!(validate_name("a/b").is_err());
+}
+
+#[tokio::test]
+async fn plans_list_changes_without_applying_them() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-test"], "running")
+            .with_ports(&[(8000, Some(8000)), (22, Some(8006))]),
+        FakeContainer::new("2", &["/pojde-stopped"], "exited"),
+    ])
+    .await;
+    let instances = docker.instances();
+
+    let options = ApplyOptions {
+        start_port: 9000,
+        recreate: true,
+        ..ApplyOptions::default()
+    };
+    let changes = instances.plan_apply("test", &options).await.unwrap();
+
+    // The fake daemon has no images
+    assert_eq!(
+        changes,
+        vec![
+            PlannedChange::new(Operation::Pull, Resource::Image, "pojntfx/pojde:latest"),
+            PlannedChange::new(Operation::Recreate, Resource::Container, "pojde-test")
+                .with_ports(Some((8000, 8006)), Some((9000, 9006))),
+        ]
+    );
+    assert_eq!(
+        changes[1].to_string(),
+        "~ container pojde-test (recreate, ports 8000-8006 -> 9000-9006)"
+    );
+
+    let changes = instances
+        .plan_apply(
+            "stopped",
+            &ApplyOptions {
+                recreate: false,
+                ..options
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(changes[1].operation, Operation::Start);
+
+    let changes = instances
+        .plan_remove(
+            &["stopped".to_owned()],
+            &RemoveScope {
+                preferences: true,
+                ..RemoveScope::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            PlannedChange::new(Operation::Remove, Resource::Container, "pojde-stopped"),
+            PlannedChange::new(
+                Operation::Remove,
+                Resource::Volume,
+                "pojde-stopped-preferences"
+            ),
+        ]
+    );
+
+    // Planning must not reach Docker's mutating endpoints
+    assert!(docker
+        .requests()
+        .iter()
+        .all(|r| r.starts_with("GET ") || r.starts_with("HEAD ")));
+}
+
+#[tokio::test]
+async fn apply_refuses_overlapping_ports() {
+    let docker = FakeDocker::start(vec![
+        // Stopped instances keep their ports reserved
+        FakeContainer::new("1", &["/pojde-first"], "exited").with_labels(&[(
+            "io.pojde.config",
+            r#"{"start_port":8000,"isolate":false,"privileged":false,"resources":{"cpus":null,"memory":null},"volumes":[]}"#,
+        )]),
+        FakeContainer::new("2", &["/postgres"], "running").with_ports(&[(5432, Some(8007))]),
+    ])
+    .await;
+    let instances = docker.instances();
+
+    assert_eq!(instances.allocate_ports("second").await.unwrap(), 8008);
+    assert_eq!(instances.next_free_ports().await.unwrap(), 8008);
+    // Existing instances keep their ports
+    assert_eq!(instances.allocate_ports("first").await.unwrap(), 8000);
+
+    let res = instances
+        .apply(
+            "second",
+            &ApplyOptions {
+                start_port: 8005,
+                ..ApplyOptions::default()
+            },
+        )
+        .await;
+
+    assert!(matches!(res, Err(Error::PortAllocated { ref name, .. }) if name == "second"));
+}
+
+#[tokio::test]
+async fn services_map_published_ports() {
+    let docker = FakeDocker::start(vec![
+        FakeContainer::new("1", &["/pojde-test"], "running")
+            .with_ports(&[(8001, Some(9001)), (22, Some(9006))]),
+        FakeContainer::new("2", &["/pojde-stopped"], "exited").with_labels(&[(
+            "io.pojde.config",
+            r#"{"start_port":8000,"isolate":false,"privileged":false,"resources":{"cpus":null,"memory":null},"volumes":[]}"#,
+        )]),
+    ])
+    .await;
+
+    let instances = docker.instances().get_instances().await.unwrap();
+
+    let services = instances[0].services();
+    assert_eq!(services.len(), 2);
+    assert_eq!(services[0].id, "code-server");
+    assert_eq!(services[0].url, "https://localhost:9001");
+    assert_eq!(services[1].url, "ssh://localhost:9006");
+
+    // Stopped instances don't publish ports
+    let services = instances[1].services();
+    assert_eq!(services.len(), 7);
+    assert_eq!(services[0].id, "cockpit");
+    assert_eq!(services[0].port, 8000);
+    assert_eq!(services[6].port, 8006);
+}
+
+#[tokio::test]
+async fn services_of_missing_instances_are_not_found() {
+    let docker = FakeDocker::start(vec![]).await;
+
+    let res = docker.instances().services("missing").await;
+
+    assert!(matches!(res, Err(Error::NotFound { ref name, .. }) if name == "missing"));
+}
+
+#[tokio::test]
+async fn apply_creates_configured_containers() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    instances
+        .apply(
+            "test",
+            &ApplyOptions {
+                start_port: 9000,
+                isolate: true,
+                modules: vec!["go".to_owned()],
+                resources: Resources {
+                    cpus: None,
+                    memory: Some(1 << 30),
+                },
+                volumes: vec!["/src:/src".to_owned()],
+                ..ApplyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let c = docker.container("pojde-test");
+    assert_eq!(c.state, "running");
+
+    let mut ports = c.ports.clone();
+    ports.sort();
+    let mut expected = vec![(22, Some(9006))];
+    expected.extend((0..6).map(|i| (8000 + i, Some(9000 + i))));
+    assert_eq!(ports, expected);
+
+    for bind in &[
+        "pojde-test-home-user:/home",
+        "pojde-ca:/opt/pojde/ca",
+        "/src:/src",
+    ] {
+        assert!(c.binds.contains(&bind.to_string()), "{} is missing", bind);
+    }
+    // Isolated instances can't reach the host's daemon
+    assert!(!c.binds.iter().any(|b| b.contains("docker.sock")));
+    assert_eq!(c.config["HostConfig"]["Memory"], 1u64 << 30);
+
+    let (_, config) = c
+        .labels
+        .iter()
+        .find(|(k, _)| k == "io.pojde.config")
+        .unwrap();
+    let config = serde_json::from_str::<serde_json::Value>(config).unwrap();
+    assert_eq!(config["start_port"], 9000);
+    assert_eq!(config["isolate"], true);
+
+    assert_eq!(
+        docker
+            .file("pojde-test", "/opt/pojde/preferences/modules.sh")
+            .unwrap(),
+        b"export POJDE_MODULES='go'\n"
+    );
+    assert!(docker
+        .file("pojde-test", "/opt/pojde/ca/instances/test/server.pem")
+        .is_some());
+    // The helper which wrote the certificate is gone
+    assert!(!docker.has_container("pojdectl-ca"));
+
+    let instance = &instances.get_instances().await.unwrap()[0];
+    assert_eq!(instance.start_port, Some(9000));
+    assert_eq!(instance.end_port, Some(9006));
+}
+
+#[tokio::test]
+async fn apply_keeps_volumes_when_recreating() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    let options = ApplyOptions {
+        start_port: 9000,
+        ..ApplyOptions::default()
+    };
+    instances.apply("test", &options).await.unwrap();
+    let ca = instances.get_ca_cert().await.unwrap();
+
+    instances
+        .apply(
+            "test",
+            &ApplyOptions {
+                start_port: 9100,
+                recreate: true,
+                ..options
+            },
+        )
+        .await
+        .unwrap();
+
+    assert!(docker
+        .container("pojde-test")
+        .ports
+        .contains(&(8000, Some(9100))));
+    assert_eq!(instances.get_ca_cert().await.unwrap(), ca);
+}
+
+#[tokio::test]
+async fn remove_deletes_containers_and_selected_volumes() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    instances
+        .apply(
+            "test",
+            &ApplyOptions {
+                start_port: 9000,
+                ..ApplyOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    instances
+        .remove(
+            &["test".to_owned()],
+            &RemoveScope {
+                user_data: true,
+                ..RemoveScope::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    assert!(!docker.has_container("pojde-test"));
+    assert_eq!(
+        docker.volumes(),
+        vec![
+            "pojde-ca",
+            "pojde-test-apt-cache",
+            "pojde-test-configuration",
+            "pojde-test-preferences",
+            "pojde-test-transfer",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn remove_keeps_the_ca_while_other_instances_use_it() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    for (name, start_port) in &[("first", 9000), ("second", 9010)] {
+        instances
+            .apply(
+                name,
+                &ApplyOptions {
+                    start_port: *start_port,
+                    ..ApplyOptions::default()
+                },
+            )
+            .await
+            .unwrap();
+    }
+
+    let deleted_volumes = || {
+        docker
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("DELETE ") && r.contains("/volumes/"))
+            .map(|r| r.rsplit('/').next().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let volumes = |name: &str| {
+        [
+            "preferences",
+            "configuration",
+            "home-root",
+            "home-user",
+            "transfer",
+            "apt-cache",
+        ]
+        .iter()
+        .map(|suffix| format!("pojde-{}-{}", name, suffix))
+        .collect::<Vec<_>>()
+    };
+
+    let first = vec!["first".to_owned()];
+    let planned = instances
+        .plan_remove(&first, &RemoveScope::all())
+        .await
+        .unwrap();
+    assert!(!planned.iter().any(|c| c.name == "pojde-ca"));
+
+    let removed = instances.remove(&first, &RemoveScope::all()).await.unwrap();
+    assert_eq!(removed, volumes("first"));
+    assert_eq!(deleted_volumes(), removed);
+
+    // The last instance takes the CA with it
+    let removed = instances
+        .remove(&["second".to_owned()], &RemoveScope::all())
+        .await
+        .unwrap();
+    let mut expected = volumes("second");
+    expected.push("pojde-ca".to_owned());
+    assert_eq!(removed, expected);
+    assert_eq!(deleted_volumes()[6..], expected[..]);
+    assert!(docker.volumes().is_empty());
+}
+
+#[tokio::test]
+async fn apply_refuses_start_ports_out_of_range() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    for start_port in &[0, 65530, u64::MAX] {
+        let options = ApplyOptions {
+            start_port: *start_port,
+            ..ApplyOptions::default()
+        };
+
+        assert!(instances.plan_apply("test", &options).await.is_err());
+        assert!(matches!(
+            instances.apply("test", &options).await,
+            Err(Error::Other { ref cause, .. }) if cause.to_string().contains("out of range")
+        ));
+    }
+
+    assert!(docker.requests().is_empty());
+}
+
+#[tokio::test]
+async fn apply_refuses_changed_options_without_recreate() {
+    let docker = FakeDocker::start(vec![]).await;
+    let instances = docker.instances();
+
+    let options = ApplyOptions {
+        start_port: 9000,
+        ..ApplyOptions::default()
+    };
+    assert_eq!(instances.apply("test", &options).await.unwrap(), 9000);
+
+    for changed in &[
+        ApplyOptions {
+            start_port: 9100,
+            ..options.clone()
+        },
+        ApplyOptions {
+            isolate: true,
+            ..options.clone()
+        },
+        ApplyOptions {
+            privileged: true,
+            ..options.clone()
+        },
+    ] {
+        assert!(matches!(
+            instances.plan_apply("test", changed).await,
+            Err(Error::Conflict { ref name, .. }) if name == "test"
+        ));
+        assert!(matches!(
+            instances.apply("test", changed).await,
+            Err(Error::Conflict { ref name, .. }) if name == "test"
+        ));
+    }
+
+    assert!(docker
+        .container("pojde-test")
+        .ports
+        .contains(&(8000, Some(9000))));
+    assert_eq!(instances.apply("test", &options).await.unwrap(), 9000);
+}
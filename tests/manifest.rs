@@ -0,0 +1,196 @@
+mod common;
+
+use chrono::Utc;
+use common::{FakeContainer, FakeDocker};
+use pojde_rs::{
+    instances::{Instance, InstanceStatus},
+    manifest::{parse_memory, plan, sync, Change, Manifest, ManifestFormat},
+};
+
+static YAML: &str = r#"
+version: 1
+instances:
+  - name: alice
+    start_port: 8000
+    modules: [go, rust]
+    isolate: true
+    resources:
+      cpus: 1.5
+      memory: 4g
+    volumes:
+      - /home/alice/src:/src
+  - name: bob
+    start_port: 8010
+"#;
+
+static TOML: &str = r#"
+version = 1
+
+[[instances]]
+name = "alice"
+start_port = 8000
+modules = ["go", "rust"]
+isolate = true
+volumes = ["/home/alice/src:/src"]
+
+[instances.resources]
+cpus = 1.5
+memory = "4g"
+
+[[instances]]
+name = "bob"
+start_port = 8010
+"#;
+
+fn instance(name: &str, manifest: Option<&Manifest>) -> Instance {
+    Instance {
+        name: name.to_owned(),
+        start_port: None,
+        end_port: None,
+        status: InstanceStatus::Running,
+        health: None,
+        uptime: None,
+        image: "pojntfx/pojde:latest".to_owned(),
+        created: Utc::now(),
+        volumes: vec![],
+        config: manifest.and_then(|m| {
+            m.instances
+                .iter()
+                .find(|i| i.name == name)
+                .map(|i| i.options(false).unwrap().config())
+        }),
+        host: "localhost".to_owned(),
+        published: vec![],
+    }
+}
+
+#[test]
+fn manifests_can_be_yaml_or_toml() {
+    let yaml = Manifest::parse(YAML, ManifestFormat::Yaml).unwrap();
+    let toml = Manifest::parse(TOML, ManifestFormat::Toml).unwrap();
+
+    assert_eq!(yaml, toml);
+
+    let options = yaml.instances[0].options(false).unwrap();
+    assert_eq!(options.modules, vec!["go", "rust"]);
+    assert!(options.isolate);
+    assert_eq!(options.resources.cpus, Some(1.5));
+    assert_eq!(options.resources.memory, Some(4 << 30));
+    assert_eq!(options.volumes, vec!["/home/alice/src:/src"]);
+}
+
+#[test]
+fn invalid_manifests_are_rejected() {
+    for (manifest, error) in [
+        ("version: 2", "unsupported manifest version"),
+        ("version: 1\nunknown: true", "unknown field"),
+        (
+            "version: 1\ninstances: [{name: a, start_port: 8000}, {name: a, start_port: 9000}]",
+            "listed more than once",
+        ),
+        (
+            "version: 1\ninstances: [{name: a, start_port: 8000}, {name: b, start_port: 8005}]",
+            "overlap with instance \"a\"",
+        ),
+        (
+            "version: 1\ninstances: [{name: a, start_port: 65530}]",
+            "ports 65530-65536 are out of range",
+        ),
+        (
+            "version: 1\ninstances: [{name: a, start_port: 18446744073709551615}]",
+            "out of range",
+        ),
+        (
+            "version: 1\ninstances: [{name: a, start_port: 8000, modules: [cobol]}]",
+            "unknown module",
+        ),
+        (
+            "version: 1\ninstances: [{name: a, start_port: 8000, volumes: [src]}]",
+            "invalid volume",
+        ),
+    ]
+    .iter()
+    {
+        let res = Manifest::parse(manifest, ManifestFormat::Yaml);
+
+        assert!(
+            matches!(&res, Err(e) if e.contains(error)),
+            "{:?} should fail with {:?}, got {:?}",
+            manifest,
+            error,
+            res
+        );
+    }
+}
+
+#[test]
+fn memory_limits_use_binary_units() {
+    assert_eq!(parse_memory("1024"), Ok(1024));
+    assert_eq!(parse_memory("512m"), Ok(512 << 20));
+    assert_eq!(parse_memory("2GB"), Ok(2 << 30));
+    assert!(parse_memory("").is_err());
+    assert!(parse_memory("0g").is_err());
+    assert!(parse_memory("4x").is_err());
+}
+
+#[test]
+fn plans_only_recreate_changed_instances() {
+    let manifest = Manifest::parse(YAML, ManifestFormat::Yaml).unwrap();
+    let mut previous = manifest.clone();
+    previous.instances[0].privileged = true;
+
+    let existing = vec![
+        instance("alice", Some(&previous)),
+        instance("bob", Some(&manifest)),
+        // Created without a manifest
+        instance("carol", None),
+    ];
+
+    assert_eq!(
+        plan(&existing[1..], &manifest, false),
+        vec![
+            ("alice".to_owned(), Change::Create),
+            ("bob".to_owned(), Change::Update),
+        ]
+    );
+    assert_eq!(
+        plan(&existing, &manifest, true),
+        vec![
+            ("alice".to_owned(), Change::Recreate),
+            ("bob".to_owned(), Change::Update),
+            ("carol".to_owned(), Change::Prune),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn sync_creates_listed_and_prunes_unlisted_instances() {
+    let docker =
+        FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-carol"], "running")]).await;
+    let instances = docker.instances();
+    let manifest = Manifest::parse(YAML, ManifestFormat::Yaml).unwrap();
+
+    let changes = plan(&instances.get_instances().await.unwrap(), &manifest, true);
+    let results = sync(&instances, &manifest, &changes, false).await;
+    assert!(results.iter().all(|(_, r)| r.is_ok()), "{:?}", results);
+
+    let alice = docker.container("pojde-alice");
+    assert!(alice.ports.contains(&(8000, Some(8000))));
+    assert!(alice.binds.contains(&"/home/alice/src:/src".to_owned()));
+    assert_eq!(alice.config["HostConfig"]["Memory"], 4u64 << 30);
+    assert!(docker
+        .container("pojde-bob")
+        .ports
+        .contains(&(22, Some(8016))));
+    assert!(!docker.has_container("pojde-carol"));
+
+    // Syncing again doesn't touch up-to-date instances
+    let changes = plan(&instances.get_instances().await.unwrap(), &manifest, true);
+    assert_eq!(
+        changes,
+        vec![
+            ("alice".to_owned(), Change::Update),
+            ("bob".to_owned(), Change::Update),
+        ]
+    );
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{FakeContainer, FakeDocker};
+use pojde_rs::{
+    output::{serialize, InstanceOutput, OperationResult, OutputFormat},
+    Error,
+};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn instances_serialize_to_stable_schema() {
+    let docker = FakeDocker::start(vec![FakeContainer::new("1", &["/pojde-test"], "exited")
+        .with_ports(&[(8000, Some(8000)), (22, Some(8006))])])
+    .await;
+
+    let instances = docker.instances().get_instances().await.unwrap();
+    let output = instances
+        .iter()
+        .map(InstanceOutput::from)
+        .collect::<Vec<_>>();
+
+    let value: Value =
+        serde_json::from_str(&serialize(OutputFormat::Json, &output).unwrap()).unwrap();
+
+    assert_eq!(
+        value,
+        json!([{
+            "name": "test",
+            "status": "exited",
+            "exit_code": 0,
+            "health": null,
+            "ports": { "start": 8000, "end": 8006 },
+            "image": "pojntfx/pojde:latest",
+            "created": "2021-06-29T20:53:20+00:00",
+            "uptime_seconds": null,
+            "volumes": [
+                "pojde-test-preferences",
+                "pojde-test-configuration",
+                "pojde-test-home-root",
+                "pojde-test-home-user",
+                "pojde-test-transfer",
+                "pojde-test-apt-cache",
+                "pojde-ca"
+            ]
+        }])
+    );
+}
+
+#[test]
+fn operation_results_include_errors() {
+    let results = vec![
+        OperationResult::new("ok", "start", &Ok(())),
+        OperationResult::new(
+            "missing",
+            "start",
+            &Err(Error::NotFound {
+                name: "missing".to_owned(),
+                cause: "no such container".into(),
+            }),
+        ),
+    ];
+
+    let yaml = serialize(OutputFormat::Yaml, &results).unwrap();
+    let value: Value = serde_yaml::from_str(&yaml).unwrap();
+
+    assert_eq!(value[0]["success"], json!(true));
+    assert_eq!(value[0]["error"], Value::Null);
+    assert_eq!(value[1]["success"], json!(false));
+    assert_eq!(
+        value[1]["error"],
+        json!("instance \"missing\" does not exist (no such container)")
+    );
+}
+
+#[test]
+fn tables_are_not_serialized() {
+    assert!(serialize(OutputFormat::Table, &[0]).is_err());
+}
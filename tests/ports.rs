@@ -0,0 +1,43 @@
+use std::collections::HashSet;
+
+use pojde_rs::ports::{PortAllocator, Reservation};
+
+fn reservation(owner: &str, start: u64, end: u64) -> Reservation {
+    Reservation {
+        owner: owner.to_owned(),
+        start,
+        end,
+    }
+}
+
+#[test]
+fn allocator_skips_reserved_ports() {
+    let allocator = PortAllocator::new(
+        vec![
+            reservation("pojde-first", 8000, 8006),
+            reservation("postgres", 8010, 8010),
+        ],
+        HashSet::new(),
+        false,
+    );
+
+    assert_eq!(allocator.next_free(8000), Some(8011));
+    assert_eq!(allocator.next_free(7000), Some(7000));
+    assert_eq!(allocator.next_free(65530), None);
+}
+
+#[test]
+fn allocator_reports_overlaps() {
+    let allocator = PortAllocator::new(
+        vec![reservation("pojde-first", 8000, 8006)],
+        HashSet::new(),
+        false,
+    );
+
+    assert_eq!(
+        allocator.check(7995),
+        Err("ports 7995-8001 overlap with ports 8000-8006 of pojde-first".to_owned())
+    );
+    assert!(allocator.check(8007).is_ok());
+    assert!(allocator.check(65535).is_err());
+}
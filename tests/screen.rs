@@ -0,0 +1,103 @@
+use pojde_rs::screen::{Color, Screen};
+
+#[test]
+fn screen_wraps_and_scrolls_text() {
+    let mut screen = Screen::new(5, 3);
+
+    screen.process(b"hello world\r\nok");
+
+    assert_eq!(screen.contents(), " worl\nd\nok");
+    assert_eq!(screen.cursor(), Some((2, 2)));
+}
+
+#[test]
+fn screen_handles_cursor_movement_and_erasing() {
+    let mut screen = Screen::new(10, 3);
+
+    screen.process(b"first\r\nsecond\x1b[1;3Hx\x1b[2;4H\x1b[K\x1b[3;1H\x1b[?25l");
+
+    assert_eq!(screen.contents(), "fixst\nsec\n");
+    assert_eq!(screen.cursor(), None);
+}
+
+#[test]
+fn screen_applies_graphic_rendition() {
+    let mut screen = Screen::new(10, 1);
+
+    screen.process(b"\x1b[1;31ma\x1b[38;5;208;48;2;1;2;3mb\x1b[0mc");
+
+    let line = screen.line(0);
+    assert_eq!(line[0].fg, Color::Indexed(1));
+    assert!(line[0].bold);
+    assert_eq!(line[1].fg, Color::Indexed(208));
+    assert_eq!(line[1].bg, Color::Rgb(1, 2, 3));
+    assert_eq!(line[2].fg, Color::Default);
+    assert!(!line[2].bold);
+}
+
+#[test]
+fn screen_restores_primary_screen_after_full_screen_programs() {
+    let mut screen = Screen::new(10, 2);
+
+    screen.process(b"$ vim\x1b[?1049h\x1b[2J\x1b[Hediting");
+    assert_eq!(screen.contents(), "editing\n");
+
+    screen.process(b"\x1b[?1049l");
+    assert_eq!(screen.contents(), "$ vim\n");
+    assert_eq!(screen.cursor(), Some((0, 5)));
+}
+
+#[test]
+fn screen_answers_cursor_position_queries() {
+    let mut screen = Screen::new(10, 5);
+
+    screen.process(b"\x1b[3;4H\x1b[6n");
+
+    assert_eq!(screen.take_responses(), b"\x1b[3;4R".to_vec());
+    assert!(screen.take_responses().is_empty());
+}
+
+#[test]
+fn screen_keeps_cursor_line_when_shrinking() {
+    let mut screen = Screen::new(10, 4);
+
+    screen.process(b"1\r\n2\r\n3\r\n4");
+    screen.resize(4, 2);
+
+    assert_eq!(screen.size(), (4, 2));
+    assert_eq!(screen.contents(), "3\n4");
+    assert_eq!(screen.cursor(), Some((1, 1)));
+}
+
+#[test]
+fn screen_copies_selected_text() {
+    let mut screen = Screen::new(10, 2);
+
+    screen.process(b"one two\r\nthree");
+
+    assert_eq!(screen.text((0, 4), (1, 2)), "two\nthr");
+    // Selections can be made backwards
+    assert_eq!(screen.text((1, 2), (0, 4)), "two\nthr");
+}
+
+#[test]
+fn screen_clamps_saved_cursor_when_shrinking() {
+    let mut screen = Screen::new(10, 40);
+
+    screen.process(b"\x1b[39;1H$ \x1b[?1049h");
+    screen.resize(10, 20);
+    screen.process(b"\x1b[?1049l$ ");
+
+    // The prompt's line stays at the bottom of the primary screen
+    assert_eq!(
+        screen.line(19)[..4].iter().map(|c| c.c).collect::<String>(),
+        "$ $ "
+    );
+    assert_eq!(screen.cursor(), Some((19, 4)));
+
+    screen.process(b"\x1b[10;5H\x1b7");
+    screen.resize(4, 3);
+    screen.process(b"\x1b8x");
+
+    assert_eq!(screen.cursor(), Some((2, 3)));
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use pojde_rs::{tasks::Tasks, Error};
+use tokio::time::sleep;
+
+async fn wait_for_idle<T: Send + 'static>(tasks: &mut Tasks<T>) -> Vec<(String, Result<T, Error>)> {
+    let mut finished = vec![];
+
+    while !tasks.is_idle() {
+        sleep(Duration::from_millis(10)).await;
+
+        finished.extend(tasks.poll().into_iter().map(|t| (t.key, t.result)));
+    }
+
+    finished
+}
+
+#[tokio::test]
+async fn tasks_report_results_by_key() {
+    let mut tasks = Tasks::default();
+
+    tasks.spawn("first", "succeed", async { Ok(1) });
+    tasks.spawn("second", "fail", async {
+        Err(Error::Other {
+            name: "second".to_owned(),
+            cause: "failed".into(),
+        })
+    });
+
+    assert!(tasks.is_running("first"));
+    assert!(tasks.is_running("second"));
+
+    let mut finished = wait_for_idle(&mut tasks).await;
+    finished.sort_by(|a, b| a.0.cmp(&b.0));
+
+    assert!(matches!(finished[0], (ref key, Ok(1)) if key == "first"));
+    assert!(matches!(finished[1], (ref key, Err(_)) if key == "second"));
+    assert!(!tasks.is_running("first"));
+}
+
+#[tokio::test]
+async fn cancelled_tasks_are_dropped() {
+    let mut tasks = Tasks::default();
+
+    tasks.spawn("slow", "wait", async {
+        sleep(Duration::from_secs(60)).await;
+
+        Ok(())
+    });
+    tasks.spawn("fast", "return", async { Ok(()) });
+
+    tasks.cancel("slow");
+    assert!(!tasks.is_running("slow"));
+
+    let finished = wait_for_idle(&mut tasks).await;
+
+    assert_eq!(finished.len(), 1);
+    assert_eq!(finished[0].0, "fast");
+}
+
+#[tokio::test]
+async fn subscriptions_yield_items_until_closed() {
+    let tasks = Tasks::<()>::default();
+
+    let mut subscription = tasks.subscribe(|sender| async move {
+        for i in 0..3 {
+            sender.send(i).ok();
+        }
+    });
+
+    let mut items = vec![];
+    while !subscription.is_closed() {
+        sleep(Duration::from_millis(10)).await;
+
+        items.extend(subscription.poll());
+    }
+
+    assert_eq!(items, vec![0, 1, 2]);
+}